@@ -3,14 +3,26 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
 
 use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
     fs::File,
-    io::{Read, Seek, SeekFrom},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
     path::Path,
+    sync::{Mutex, MutexGuard, PoisonError},
 };
 
+use bytes::Bytes;
+use encoding_rs::{GBK, SHIFT_JIS};
 use log::info;
+use memmap2::Mmap;
 
-use crate::error::{ArcError, ArcResult};
+use crate::{
+    EncodeOptions, EntryKind, ImageFormat,
+    codec::CodecRegistry,
+    encode_for_pack,
+    error::{ArcError, ArcResult},
+};
 
 /// ARC archive format version.
 ///
@@ -26,7 +38,7 @@ pub enum ArcVersion {
 
 impl ArcVersion {
     /// Detect the version from a 12-byte magic signature.
-    fn from_magic(magic: &[u8]) -> Option<Self> {
+    pub(crate) fn from_magic(magic: &[u8]) -> Option<Self> {
         match magic {
             b"PackFile    " => Some(Self::V1),
             b"BURIKO ARC20" => Some(Self::V2),
@@ -65,56 +77,436 @@ impl ArcVersion {
     }
 }
 
+/// Character set used for entry names in the archive index.
+///
+/// Japanese titles use CP932 (Shift-JIS); Chinese fan translations are
+/// commonly repacked with GBK names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameEncoding {
+    #[default]
+    Cp932,
+    Gbk,
+    Utf8,
+}
+
+impl NameEncoding {
+    /// Decode a null-padded name field into a display / output file name.
+    ///
+    /// Bytes that are invalid in this charset become U+FFFD, and control
+    /// characters (which cannot appear in a sane file name) are replaced with
+    /// underscores.
+    #[must_use]
+    pub fn decode(self, raw: &[u8]) -> String {
+        let raw = trim_name(raw);
+        let decoded = match self {
+            Self::Cp932 => SHIFT_JIS.decode_without_bom_handling(raw).0,
+            Self::Gbk => GBK.decode_without_bom_handling(raw).0,
+            Self::Utf8 => String::from_utf8_lossy(raw),
+        };
+        decoded
+            .chars()
+            .map(|c| if c.is_control() { '_' } else { c })
+            .collect()
+    }
+
+    /// Encode a UTF-8 entry name in this charset, truncated (on a character
+    /// boundary) to fit a `limit`-byte name field.
+    pub fn encode(self, name: &str, limit: usize) -> ArcResult<Vec<u8>> {
+        let encoded = self
+            .encode_str(name)
+            .ok_or_else(|| ArcError::UnencodableFileName(name.to_owned(), self))?;
+        if encoded.len() <= limit {
+            return Ok(encoded.into_owned());
+        }
+
+        let mut end = name.len();
+        loop {
+            end = name.floor_char_boundary(end - 1);
+            if let Some(encoded) = self.encode_str(&name[..end])
+                && encoded.len() <= limit
+            {
+                return Ok(encoded.into_owned());
+            }
+        }
+    }
+
+    /// Encode `name`, returning `None` if it contains unmappable characters.
+    fn encode_str(self, name: &str) -> Option<Cow<'_, [u8]>> {
+        let encoding = match self {
+            Self::Cp932 => SHIFT_JIS,
+            Self::Gbk => GBK,
+            Self::Utf8 => return Some(Cow::Borrowed(name.as_bytes())),
+        };
+        let (encoded, _, unmappable) = encoding.encode(name);
+        (!unmappable).then_some(encoded)
+    }
+}
+
+impl fmt::Display for NameEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cp932 => write!(f, "CP932"),
+            Self::Gbk => write!(f, "GBK"),
+            Self::Utf8 => write!(f, "UTF-8"),
+        }
+    }
+}
+
+impl TryFrom<&str> for NameEncoding {
+    type Error = ArcError;
+
+    fn try_from(v: &str) -> Result<Self, Self::Error> {
+        match v.to_ascii_lowercase().as_str() {
+            "cp932" | "sjis" | "shift-jis" | "shift_jis" => Ok(Self::Cp932),
+            "gbk" | "cp936" => Ok(Self::Gbk),
+            "utf8" | "utf-8" => Ok(Self::Utf8),
+            _ => Err(ArcError::InvalidNameEncoding(v.to_owned())),
+        }
+    }
+}
+
 /// Represents a single file entry within an ARC archive.
 #[derive(Debug, Clone)]
 pub struct ArcFile {
+    /// Null-padded name field exactly as stored in the index (usually CP932).
     pub name: Vec<u8>,
     pub offset: u32,
     pub size: u32,
+    /// Index bytes after the offset and size. Usually zero; written back
+    /// zero-filled to the version's width.
+    pub padding: Vec<u8>,
+}
+
+/// Metadata of one archive entry, as yielded by [`Arc::entries`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInfo<'a> {
+    pub index: u32,
+    /// Name decoded with the archive's [`NameEncoding`].
+    pub name: &'a str,
+    /// On-disk name bytes, up to (not including) the first null byte.
+    pub raw_name: &'a [u8],
+    /// Absolute position of the entry's data within the reader.
+    pub offset: u64,
+    pub size: u32,
+    /// Content kind, sniffed from the entry's first bytes.
+    pub kind: EntryKind,
+    /// Whether the content is wrapped in BSE encryption.
+    pub bse: bool,
+}
+
+/// Iterator over the entries of an [`Arc`], created by [`Arc::entries`].
+pub struct Entries<'a, R> {
+    arc: &'a Arc<R>,
+    next: u32,
+}
+
+impl<'a, R: Read + Seek> Iterator for Entries<'a, R> {
+    type Item = EntryInfo<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next;
+        if index >= self.arc.count {
+            return None;
+        }
+        self.next += 1;
+        Some(self.arc.entry_info(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.arc.count - self.next) as usize;
+        (left, Some(left))
+    }
+}
+
+impl<R: Read + Seek> ExactSizeIterator for Entries<'_, R> {}
+
+// `Arc::entries` is the `iter` method here.
+#[allow(clippy::into_iter_without_iter)]
+impl<'a, R: Read + Seek> IntoIterator for &'a Arc<R> {
+    type Item = EntryInfo<'a>;
+    type IntoIter = Entries<'a, R>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries()
+    }
+}
+
+/// `Read + Seek` view of a single entry's bytes, created by
+/// [`Arc::open_entry`].
+///
+/// Positions are relative to the start of the entry, and reads stop at its
+/// end. Every read locks the archive's reader for one seek + read, so several
+/// entry readers can be used at once, also from different threads.
+pub struct EntryReader<'a, R> {
+    arc: &'a Arc<R>,
+    /// Absolute start of the entry within the archive's reader.
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> EntryReader<'_, R> {
+    /// Size of the entry in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the entry is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<R: Read + Seek> Read for EntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let want = usize::try_from(left).map_or(buf.len(), |left| left.min(buf.len()));
+        let n = self.arc.read_at(self.start + self.pos, &mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for EntryReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(delta) => (self.len, delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        self.pos = base.checked_add_signed(delta).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
 }
 
 /// ARC archive reader.
-pub struct Arc {
-    file: File,
-    data_offset: u32,
+///
+/// Generic over any `Read + Seek` source: [`Arc::open`] reads from a file on
+/// disk, while [`Arc::from_reader`] accepts in-memory buffers (`Cursor`) or
+/// archives nested inside another container.
+///
+/// All accessors take `&self`, so an archive can be shared across rayon
+/// workers. Archives backed by [`Bytes`] (see [`Arc::open_mmap`] and
+/// [`Arc::from_bytes`]) additionally hand out zero-copy entry slices.
+pub struct Arc<R = File> {
+    /// Guards the seek position; held only for one seek + read.
+    reader: Mutex<R>,
+    /// Absolute position of the data region within `reader`.
+    data_offset: u64,
+    /// Absolute position of the end of `reader`, as measured at open time.
+    end: u64,
     count: u32,
     version: ArcVersion,
     files: Vec<ArcFile>,
+    name_encoding: NameEncoding,
+    /// Decoded display names, parallel to `files`.
+    names: Vec<String>,
+    /// Decoded name → first entry with that name.
+    by_name: HashMap<String, u32>,
+    /// Lowercased name → first entry, if case-insensitive lookup is enabled.
+    by_folded_name: Option<HashMap<String, u32>>,
 }
 
-impl Arc {
+impl Arc<File> {
     /// Open an ARC file and parse its index.
     pub fn open<P: AsRef<Path>>(filename: P) -> ArcResult<Self> {
-        let mut file = File::open(&filename)?;
+        Self::from_reader(File::open(&filename)?)
+    }
+
+    /// Open an ARC file without checking that entries lie within the file.
+    ///
+    /// See [`Arc::from_reader_unchecked`].
+    pub fn open_unchecked<P: AsRef<Path>>(filename: P) -> ArcResult<Self> {
+        Self::from_reader_unchecked(File::open(&filename)?)
+    }
+
+    /// Memory-map an ARC file and parse its index.
+    ///
+    /// Entries are then available as zero-copy [`Bytes`] handles via
+    /// [`Arc::get_file_bytes`]. The file must not be truncated or modified
+    /// while the archive (or any handle obtained from it) is alive.
+    pub fn open_mmap<P: AsRef<Path>>(filename: P) -> ArcResult<Arc<Cursor<Bytes>>> {
+        let file = File::open(&filename)?;
+        // SAFETY: the mapping is read-only; callers are told not to modify the
+        // file while it is mapped, same as with any other memory-mapped reader.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Arc::from_bytes(Bytes::from_owner(mmap))
+    }
+}
+
+impl Arc<Cursor<Bytes>> {
+    /// Parse an archive held in memory.
+    pub fn from_bytes(data: impl Into<Bytes>) -> ArcResult<Self> {
+        Self::from_reader(Cursor::new(data.into()))
+    }
+
+    /// Returns the data for the file at the given index without copying it.
+    ///
+    /// The returned handle shares the archive's buffer and is cheap to clone
+    /// and send to other threads.
+    pub fn get_file_bytes(&self, idx: u32) -> ArcResult<Bytes> {
+        let (start, len) = self.entry_range(idx)?;
+        let buffer = self.lock().get_ref().clone();
+        let start = usize::try_from(start).unwrap_or(usize::MAX);
+        match start.checked_add(len) {
+            Some(end) if end <= buffer.len() => Ok(buffer.slice(start..end)),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
+
+impl<R: Read + Seek> Arc<R> {
+    /// Parse the index of an archive starting at the reader's current
+    /// position.
+    ///
+    /// Entry offsets are resolved relative to that start position, so an
+    /// archive embedded in a larger stream can be read without copying it out.
+    ///
+    /// The entry count and every entry's offset and size are checked against
+    /// the length of the stream, failing with [`ArcError::TooManyEntries`] or
+    /// [`ArcError::EntryOutOfBounds`].
+    pub fn from_reader(reader: R) -> ArcResult<Self> {
+        let arc = Self::from_reader_unchecked(reader)?;
+        for (i, file) in arc.files.iter().enumerate() {
+            let start = arc.data_offset + u64::from(file.offset);
+            if start + u64::from(file.size) > arc.end {
+                return Err(ArcError::EntryOutOfBounds(
+                    i as u32,
+                    arc.names[i].clone(),
+                    start,
+                    file.size,
+                ));
+            }
+        }
+        Ok(arc)
+    }
+
+    /// Like [`Arc::from_reader`], but only checks the entry count, not the
+    /// entries themselves.
+    ///
+    /// Reading an entry that lies outside the stream still fails with
+    /// [`ArcError::EntryOutOfBounds`]. Useful for inspecting damaged archives,
+    /// e.g. with [`Arc::check`].
+    pub fn from_reader_unchecked(mut reader: R) -> ArcResult<Self> {
+        let base = reader.stream_position()?;
 
         // Read and validate the magic signature
         let mut magic_string = [0u8; 12];
-        file.read_exact(&mut magic_string)?;
+        reader.read_exact(&mut magic_string)?;
 
         let version = ArcVersion::from_magic(&magic_string).ok_or(ArcError::InvalidFormat)?;
         info!("ARC version: {version:?}");
 
         // Read the number of file entries
         let mut buffer = [0u8; 4];
-        file.read_exact(&mut buffer)?;
+        reader.read_exact(&mut buffer)?;
         let number_of_files = u32::from_le_bytes(buffer);
 
+        // The index must fit in the stream before anything is allocated for it.
+        let end = reader.seek(SeekFrom::End(0))?;
+        let room = end.saturating_sub(base + 16) / u64::from(version.metadata_size());
+        if u64::from(number_of_files) > room {
+            return Err(ArcError::TooManyEntries(number_of_files, room));
+        }
+        reader.seek(SeekFrom::Start(base + 16))?;
+
         // Read all file metadata entries
         let mut files = Vec::with_capacity(number_of_files as usize);
         for _ in 0..number_of_files {
-            let file_info = Self::read_metadata(&mut file, version)?;
+            let file_info = Self::read_metadata(&mut reader, version)?;
             files.push(file_info);
         }
 
-        let data_position = file.stream_position()? as u32;
+        let data_offset = reader.stream_position()?;
+        Ok(Self::from_parts(reader, version, data_offset, end, files))
+    }
 
-        Ok(Arc {
-            file,
-            data_offset: data_position,
-            count: number_of_files,
+    /// Assemble an archive from an already parsed index.
+    pub(crate) fn from_parts(
+        reader: R,
+        version: ArcVersion,
+        data_offset: u64,
+        end: u64,
+        files: Vec<ArcFile>,
+    ) -> Self {
+        let name_encoding = NameEncoding::default();
+        let names: Vec<String> = files
+            .iter()
+            .map(|f| name_encoding.decode(&f.name))
+            .collect();
+        let by_name = name_index(&names, false);
+
+        Arc {
+            reader: Mutex::new(reader),
+            data_offset,
+            end,
+            count: files.len() as u32,
             version,
             files,
-        })
+            name_encoding,
+            names,
+            by_name,
+            by_folded_name: None,
+        }
+    }
+
+    /// Consume the archive, returning the reader, the absolute data region
+    /// start and the parsed index.
+    pub(crate) fn into_parts(self) -> (R, u64, Vec<ArcFile>) {
+        let data_offset = self.data_offset;
+        let files = self.files;
+        let reader = self
+            .reader
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        (reader, data_offset, files)
+    }
+
+    /// Consume the archive, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Re-decode every entry name with the given charset (CP932 by default).
+    #[must_use]
+    pub fn with_name_encoding(mut self, encoding: NameEncoding) -> Self {
+        self.name_encoding = encoding;
+        self.names = self
+            .files
+            .iter()
+            .map(|f| encoding.decode(&f.name))
+            .collect();
+        self.by_name = name_index(&self.names, false);
+        if self.by_folded_name.is_some() {
+            self.by_folded_name = Some(name_index(&self.names, true));
+        }
+        self
+    }
+
+    /// Make [`Arc::find`] and [`Arc::entry`] fall back to case-insensitive
+    /// matching when there is no exact match.
+    #[must_use]
+    pub fn with_case_insensitive_lookup(mut self, enabled: bool) -> Self {
+        self.by_folded_name = enabled.then(|| name_index(&self.names, true));
+        self
+    }
+
+    /// Returns the charset used to decode entry names.
+    #[must_use]
+    pub fn name_encoding(&self) -> NameEncoding {
+        self.name_encoding
     }
 
     /// Returns the total number of files in the archive.
@@ -129,25 +521,41 @@ impl Arc {
         self.version
     }
 
-    /// Read the raw data for the file at the given index.
+    /// Read the raw data for the file at the given index into a new buffer.
     ///
-    /// Takes `&mut self` because reading requires seeking the underlying file
-    /// descriptor. This avoids the overhead of `File::try_clone()` per call.
-    pub fn get_file_data(&mut self, idx: u32) -> ArcResult<Vec<u8>> {
-        if idx >= self.count {
-            return Err(ArcError::IndexOutOfBounds(idx, self.count));
-        }
+    /// The reader is locked only for the seek + read, so concurrent callers
+    /// serialize on I/O but not on anything else.
+    pub fn get_file_data(&self, idx: u32) -> ArcResult<Vec<u8>> {
+        let (start, len) = self.entry_range(idx)?;
+        let mut data = vec![0u8; len];
 
-        let file_info = &self.files[idx as usize];
-        let mut data = vec![0u8; file_info.size as usize];
+        let mut reader = self.lock();
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(&mut data)?;
 
-        self.file.seek(SeekFrom::Start(
-            u64::from(self.data_offset) + u64::from(file_info.offset),
-        ))?;
+        Ok(data)
+    }
 
-        self.file.read_exact(&mut data)?;
+    /// Iterate over all entries in index order.
+    ///
+    /// Each step reads the first [`EntryKind::SNIFF_LEN`] bytes of the entry
+    /// to sniff its kind; an entry that cannot be read is reported as
+    /// [`EntryKind::Raw`].
+    #[must_use]
+    pub fn entries(&self) -> Entries<'_, R> {
+        Entries { arc: self, next: 0 }
+    }
 
-        Ok(data)
+    /// Open the file at the given index as a bounded `Read + Seek` stream,
+    /// without reading it into memory.
+    pub fn open_entry(&self, idx: u32) -> ArcResult<EntryReader<'_, R>> {
+        let (start, len) = self.entry_range(idx)?;
+        Ok(EntryReader {
+            arc: self,
+            start,
+            len: len as u64,
+            pos: 0,
+        })
     }
 
     /// Returns the original (compressed) size for the file at the given index.
@@ -158,49 +566,415 @@ impl Arc {
         Ok(self.files[idx as usize].size)
     }
 
-    /// Returns the null-terminated filename for the file at the given index.
+    /// Returns the display filename for the file at the given index, decoded
+    /// with the archive's [`NameEncoding`].
+    ///
+    /// The decoding is lossy; use [`Arc::get_raw_name`] to get the exact bytes.
     pub fn get_file_name(&self, idx: u32) -> ArcResult<&str> {
         if idx >= self.count {
             return Err(ArcError::IndexOutOfBounds(idx, self.count));
         }
+        Ok(&self.names[idx as usize])
+    }
 
-        let name_bytes = &self.files[idx as usize].name;
-        // Find the first null byte
-        let len = name_bytes
-            .iter()
-            .position(|&b| b == 0)
-            .unwrap_or(name_bytes.len());
+    /// Returns the index of the entry with the given decoded name.
+    ///
+    /// A trailing `.png` or `.ogg`, as added by [`crate::decode_file`], is
+    /// ignored if the full name does not match. If several entries share a
+    /// name, the first one is returned.
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.lookup(name).or_else(|| {
+            let (stem, ext) = name.rsplit_once('.')?;
+            if ext.eq_ignore_ascii_case("png") || ext.eq_ignore_ascii_case("ogg") {
+                self.lookup(stem)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Read the raw data of the entry with the given name, matched as in
+    /// [`Arc::find`].
+    pub fn entry(&self, name: &str) -> ArcResult<Vec<u8>> {
+        let idx = self
+            .find(name)
+            .ok_or_else(|| ArcError::EntryNotFound(name.to_owned()))?;
+        self.get_file_data(idx)
+    }
+
+    /// Returns the on-disk name bytes for the file at the given index, up to
+    /// (not including) the first null byte.
+    pub fn get_raw_name(&self, idx: u32) -> ArcResult<&[u8]> {
+        if idx >= self.count {
+            return Err(ArcError::IndexOutOfBounds(idx, self.count));
+        }
+        Ok(trim_name(&self.files[idx as usize].name))
+    }
+
+    /// Parsed index entries.
+    pub(crate) fn files(&self) -> &[ArcFile] {
+        &self.files
+    }
+
+    /// Absolute position of the data region within the reader.
+    pub(crate) fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
 
-        Ok(std::str::from_utf8(&name_bytes[..len])?)
+    /// Read up to `buf.len()` bytes at an absolute reader position, returning
+    /// how many were available.
+    pub(crate) fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = self.lock();
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match reader.read(&mut buf[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(filled)
+    }
+
+    /// Absolute position of the end of the underlying stream.
+    pub(crate) fn stream_end(&self) -> u64 {
+        self.end
+    }
+
+    /// Absolute start position and length of the file at the given index.
+    fn entry_range(&self, idx: u32) -> ArcResult<(u64, usize)> {
+        if idx >= self.count {
+            return Err(ArcError::IndexOutOfBounds(idx, self.count));
+        }
+        let file_info = &self.files[idx as usize];
+        let start = self.data_offset + u64::from(file_info.offset);
+        if start + u64::from(file_info.size) > self.end {
+            return Err(ArcError::EntryOutOfBounds(
+                idx,
+                self.names[idx as usize].clone(),
+                start,
+                file_info.size,
+            ));
+        }
+        Ok((start, file_info.size as usize))
+    }
+
+    fn entry_info(&self, index: u32) -> EntryInfo<'_> {
+        let file = &self.files[index as usize];
+        let offset = self.data_offset + u64::from(file.offset);
+
+        let mut prefix = [0u8; EntryKind::SNIFF_LEN];
+        let len = (file.size as usize).min(prefix.len());
+        let (kind, bse) = match self.read_at(offset, &mut prefix[..len]) {
+            Ok(n) => EntryKind::sniff(&prefix[..n]),
+            Err(_) => (EntryKind::Raw, false),
+        };
+
+        EntryInfo {
+            index,
+            name: &self.names[index as usize],
+            raw_name: trim_name(&file.name),
+            offset,
+            size: file.size,
+            kind,
+            bse,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied().or_else(|| {
+            self.by_folded_name
+                .as_ref()?
+                .get(&name.to_lowercase())
+                .copied()
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, R> {
+        self.reader.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
     /// Read a metadata entry.
     ///
     /// Layout: [name (`name_len`)][4 offset][4 size][trailing padding]
-    fn read_metadata(file: &mut File, version: ArcVersion) -> ArcResult<ArcFile> {
+    pub(crate) fn read_metadata(reader: &mut R, version: ArcVersion) -> ArcResult<ArcFile> {
         let mut name = vec![0u8; version.name_len()];
-        file.read_exact(&mut name)?;
-
-        sanitize_name(&mut name);
+        reader.read_exact(&mut name)?;
 
         let mut buffer = [0u8; 8];
-        file.read_exact(&mut buffer)?;
+        reader.read_exact(&mut buffer)?;
         let offset = u32::from_le_bytes(buffer[0..4].try_into().unwrap());
         let size = u32::from_le_bytes(buffer[4..8].try_into().unwrap());
 
-        // Skip trailing padding (V1: 8 bytes, V2: 24 bytes)
-        let padding = i64::from(version.metadata_size()) - 8 - version.name_len() as i64;
-        file.seek(SeekFrom::Current(padding))?;
+        // Trailing padding (V1: 8 bytes, V2: 24 bytes)
+        let mut padding = vec![0u8; version.metadata_size() as usize - 8 - version.name_len()];
+        reader.read_exact(&mut padding)?;
 
-        Ok(ArcFile { name, offset, size })
+        Ok(ArcFile {
+            name,
+            offset,
+            size,
+            padding,
+        })
     }
 }
 
-/// Replace non-printable-ASCII bytes in the name buffer with underscores.
-fn sanitize_name(name: &mut [u8]) {
-    for b in name.iter_mut() {
-        if *b != 0 && (*b < 32 || *b > 127) {
-            *b = b'_';
+/// Map each name to the first index it appears at, lowercased if `fold`.
+fn name_index(names: &[String], fold: bool) -> HashMap<String, u32> {
+    let mut index = HashMap::with_capacity(names.len());
+    for (i, name) in names.iter().enumerate() {
+        let key = if fold {
+            name.to_lowercase()
+        } else {
+            name.clone()
+        };
+        index.entry(key).or_insert(i as u32);
+    }
+    index
+}
+
+/// Incremental ARC archive writer.
+///
+/// Entries are streamed straight into the output as they are added, so only
+/// one payload is held in memory at a time. The header and index are written
+/// by [`ArcWriter::finish`]; until then the space in front of the data region
+/// is reserved for the number of entries given to [`ArcWriter::with_capacity`].
+/// If the final entry count differs, `finish` shifts the data region once.
+pub struct ArcWriter<W: Read + Write + Seek> {
+    writer: W,
+    /// Absolute position of the archive header within `writer`.
+    base: u64,
+    version: ArcVersion,
+    name_encoding: NameEncoding,
+    encode: EncodeOptions,
+    codecs: CodecRegistry,
+    /// Index slots reserved in front of the data region.
+    reserved: u64,
+    entries: Vec<ArcFile>,
+    data_len: u64,
+}
+
+impl<W: Read + Write + Seek> ArcWriter<W> {
+    /// Start an archive at the writer's current position.
+    pub fn new(writer: W, version: ArcVersion) -> ArcResult<Self> {
+        Self::with_capacity(writer, version, 0)
+    }
+
+    /// Start an archive, reserving index space for `entries` entries so that
+    /// `finish` does not need to move any data.
+    ///
+    /// Reserving more entries than are added leaves stale bytes past the end
+    /// of the finished archive; truncate the output if that matters.
+    pub fn with_capacity(mut writer: W, version: ArcVersion, entries: u32) -> ArcResult<Self> {
+        let base = writer.stream_position()?;
+        let reserved = u64::from(entries);
+        writer.seek(SeekFrom::Start(base + index_len(version, reserved)))?;
+        Ok(Self {
+            writer,
+            base,
+            version,
+            name_encoding: NameEncoding::default(),
+            encode: EncodeOptions::default(),
+            codecs: CodecRegistry::builtin().clone(),
+            reserved,
+            entries: Vec::with_capacity(entries as usize),
+            data_len: 0,
+        })
+    }
+
+    /// Charset used by [`ArcWriter::add_entry`] and [`ArcWriter::add_encoded`]
+    /// (CP932 by default).
+    #[must_use]
+    pub fn with_name_encoding(mut self, encoding: NameEncoding) -> Self {
+        self.name_encoding = encoding;
+        self
+    }
+
+    /// Encoding applied to PNG images by [`ArcWriter::add_entry`].
+    #[must_use]
+    pub fn with_image_format(mut self, format: ImageFormat) -> Self {
+        self.encode.image_format = Some(format);
+        self
+    }
+
+    /// Conversion applied to payloads by [`ArcWriter::add_entry`]. Replaces
+    /// any format set with [`ArcWriter::with_image_format`].
+    #[must_use]
+    pub fn with_encode_options(mut self, options: EncodeOptions) -> Self {
+        self.encode = options;
+        self
+    }
+
+    /// Codecs used by [`ArcWriter::add_entry`] (the built-in ones by
+    /// default).
+    #[must_use]
+    pub fn with_codecs(mut self, codecs: CodecRegistry) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Number of entries added so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no entries have been added.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Convert a source file (PNG, OGG, anything else as-is) the same way
+    /// [`crate::pack_arc`] does and add it under `name`.
+    pub fn add_entry(&mut self, name: &str, data: &[u8]) -> ArcResult<()> {
+        let name = self.name_encoding.encode(name, self.version.name_len())?;
+        self.add_entry_raw(&name, data)
+    }
+
+    /// Like [`ArcWriter::add_entry`], with the name given as raw index bytes.
+    pub fn add_entry_raw(&mut self, name: &[u8], data: &[u8]) -> ArcResult<()> {
+        let display_name = self.name_encoding.decode(name);
+        let (encoded, _) = encode_for_pack(&display_name, data, self.encode, &self.codecs)?;
+        self.add_encoded_raw(name, encoded.as_slice())
+    }
+
+    /// Add an already-encoded payload (e.g. an entry copied from another
+    /// archive), streaming it from `reader` without conversion.
+    pub fn add_encoded(&mut self, name: &str, reader: impl Read) -> ArcResult<()> {
+        let name = self.name_encoding.encode(name, self.version.name_len())?;
+        self.add_encoded_raw(&name, reader)
+    }
+
+    /// Like [`ArcWriter::add_encoded`], with the name given as raw index bytes.
+    pub fn add_encoded_raw(&mut self, name: &[u8], reader: impl Read) -> ArcResult<()> {
+        let name_len_limit = self.version.name_len();
+        if name.len() > name_len_limit {
+            return Err(ArcError::FileNameTooLong(name.len(), name_len_limit));
+        }
+        let (offset, size) = self.add_data(reader)?;
+        self.add_index_entry(ArcFile {
+            name: name.to_vec(),
+            offset,
+            size,
+            padding: Vec::new(),
+        })
+    }
+
+    /// Append bytes from `reader` to the data region without indexing them,
+    /// returning their offset and size.
+    ///
+    /// Together with [`ArcWriter::add_index_entry`] this lays out the data
+    /// region freely: entries can share one payload, be indexed in a
+    /// different order than their payloads, or leave unindexed bytes between
+    /// payloads.
+    pub fn add_data(&mut self, mut reader: impl Read) -> ArcResult<(u32, u32)> {
+        let offset = u32::try_from(self.data_len).map_err(|_| ArcError::ArchiveTooLarge)?;
+        let size = io::copy(&mut reader, &mut self.writer)?;
+        let size = u32::try_from(size).map_err(|_| ArcError::ArchiveTooLarge)?;
+        self.data_len += u64::from(size);
+        Ok((offset, size))
+    }
+
+    /// Add an index entry for data already written. The name field is
+    /// null-padded to the version's width; padding beyond the version's width
+    /// is dropped.
+    pub fn add_index_entry(&mut self, mut entry: ArcFile) -> ArcResult<()> {
+        let name_len_limit = self.version.name_len();
+        if entry.name.len() > name_len_limit {
+            return Err(ArcError::FileNameTooLong(entry.name.len(), name_len_limit));
         }
+        if u64::from(entry.offset) + u64::from(entry.size) > self.data_len {
+            return Err(ArcError::EntryOutOfBounds(
+                self.entries.len() as u32,
+                String::from_utf8_lossy(trim_name(&entry.name)).into_owned(),
+                u64::from(entry.offset),
+                entry.size,
+            ));
+        }
+        entry.name.resize(name_len_limit, 0);
+        self.entries.push(entry);
+        Ok(())
     }
+
+    /// Write the header and index, returning the underlying writer positioned
+    /// at the end of the archive.
+    pub fn finish(mut self) -> ArcResult<W> {
+        let count = self.entries.len() as u64;
+        let old_start = self.base + index_len(self.version, self.reserved);
+        let new_start = self.base + index_len(self.version, count);
+        if old_start != new_start {
+            move_region(&mut self.writer, old_start, new_start, self.data_len)?;
+        }
+
+        self.writer.seek(SeekFrom::Start(self.base))?;
+        write_index(&mut self.writer, self.version, &self.entries)?;
+        self.writer
+            .seek(SeekFrom::Start(new_start + self.data_len))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Write the header and index for `entries` at the writer's position.
+pub(crate) fn write_index(
+    writer: &mut impl Write,
+    version: ArcVersion,
+    entries: &[ArcFile],
+) -> io::Result<()> {
+    let count = entries.len() as u64;
+    let mut index = Vec::with_capacity(index_len(version, count) as usize);
+    index.extend_from_slice(version.magic());
+    index.extend_from_slice(&(count as u32).to_le_bytes());
+
+    // Per-file metadata entries.
+    // V1: [16-byte name][4 offset][4 size][8 padding] = 32 bytes
+    // V2: [96-byte name][4 offset][4 size][24 padding] = 128 bytes
+    let padding = version.metadata_size() as usize - 8 - version.name_len();
+    for entry in entries {
+        index.extend_from_slice(&entry.name);
+        index.extend_from_slice(&entry.offset.to_le_bytes());
+        index.extend_from_slice(&entry.size.to_le_bytes());
+        let kept = entry.padding.len().min(padding);
+        index.extend_from_slice(&entry.padding[..kept]);
+        index.extend(std::iter::repeat_n(0u8, padding - kept));
+    }
+    writer.write_all(&index)
+}
+
+/// Size of the header plus an index of `count` entries.
+pub(crate) fn index_len(version: ArcVersion, count: u64) -> u64 {
+    16 + count * u64::from(version.metadata_size())
+}
+
+/// Move `len` bytes from `from` to `to` within a stream, chunk by chunk, in
+/// the order that never overwrites bytes not yet copied.
+pub(crate) fn move_region<S: Read + Write + Seek>(
+    stream: &mut S,
+    from: u64,
+    to: u64,
+    len: u64,
+) -> io::Result<()> {
+    const CHUNK: u64 = 1 << 16;
+    let mut buf = vec![0u8; CHUNK.min(len) as usize];
+    let mut done = 0u64;
+    while done < len {
+        let n = CHUNK.min(len - done);
+        // Growing: copy from the end backwards. Shrinking: from the front.
+        let rel = if to > from { len - done - n } else { done };
+        let chunk = &mut buf[..n as usize];
+        stream.seek(SeekFrom::Start(from + rel))?;
+        stream.read_exact(chunk)?;
+        stream.seek(SeekFrom::Start(to + rel))?;
+        stream.write_all(chunk)?;
+        done += n;
+    }
+    Ok(())
+}
+
+/// Cut a null-padded name field at its first null byte.
+pub(crate) fn trim_name(raw: &[u8]) -> &[u8] {
+    let len = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    &raw[..len]
 }
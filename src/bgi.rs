@@ -1,4 +1,4 @@
-//! BGI uncompressed image format decoder.
+//! BGI uncompressed image format decoder and encoder.
 //!
 //! Ported from `GARBro`'s ImageBGI.cs. Supports both plain and scrambled
 //! (`RestorePixels` snake-delta) modes.
@@ -10,7 +10,7 @@ use log::debug;
 
 use crate::{
     error::ArcResult,
-    write::{convert_bgr_to_rgba, write_rgba_to_png},
+    write::{PixelLayout, convert_bgr_to_rgba, write_rgba_to_png},
 };
 
 // ---------------------------------------------------------------------------
@@ -96,36 +96,72 @@ pub fn save(data: &[u8], width: u16, height: u16, savepath: impl AsRef<Path>) ->
 /// Encode RGBA pixels into a BGI uncompressed image buffer.
 ///
 /// Uses `flag = 0` (plain mode) so no snake-delta scrambling is needed.
-/// Selects 24 bpp when every pixel is opaque, otherwise 32 bpp (BGRA).
+/// `layout` picks the stored bpp.
 #[must_use]
-pub fn encode_bgi(rgba: &[u8], width: u16, height: u16, has_alpha: bool) -> Vec<u8> {
-    let bpp: u16 = if has_alpha { 32 } else { 24 };
-    let pixel_size = usize::from(bpp / 8);
-    let total = usize::from(width) * usize::from(height);
+pub fn encode_bgi(rgba: &[u8], width: u16, height: u16, layout: PixelLayout) -> Vec<u8> {
+    encode(rgba, width, height, layout, false)
+}
+
+/// Encode RGBA pixels into a scrambled (`flag = 1`) BGI image buffer.
+///
+/// Each channel is stored as wrapping deltas along a snake path (even rows
+/// left to right, odd rows right to left), the inverse of
+/// [`restore_pixels`]. The result is the same size as [`encode_bgi`]'s but
+/// compresses much better when DSC-wrapped.
+#[must_use]
+pub fn encode_bgi_scrambled(rgba: &[u8], width: u16, height: u16, layout: PixelLayout) -> Vec<u8> {
+    encode(rgba, width, height, layout, true)
+}
+
+fn encode(rgba: &[u8], width: u16, height: u16, layout: PixelLayout, scrambled: bool) -> Vec<u8> {
+    let bpp = layout.bpp();
+    let pixels = layout.pack(rgba);
 
-    let mut output = Vec::with_capacity(0x10 + total * pixel_size);
+    let mut output = Vec::with_capacity(0x10 + pixels.len());
 
     // 16-byte header
     output.extend_from_slice(&width.to_le_bytes());
     output.extend_from_slice(&height.to_le_bytes());
     output.extend_from_slice(&bpp.to_le_bytes());
-    output.extend_from_slice(&0u16.to_le_bytes()); // flag = 0 (plain)
+    output.extend_from_slice(&u16::from(scrambled).to_le_bytes()); // flag
     output.extend_from_slice(&[0u8; 8]); // reserved
 
-    // RGBA → BGR(A)
-    for i in 0..total {
-        let off = i * 4;
-        output.push(rgba[off + 2]); // B
-        output.push(rgba[off + 1]); // G
-        output.push(rgba[off]); // R
-        if has_alpha {
-            output.push(rgba[off + 3]); // A
-        }
+    if scrambled {
+        scramble_pixels(
+            &pixels,
+            &mut output,
+            usize::from(width),
+            usize::from(height),
+            layout.pixel_size(),
+        );
+    } else {
+        output.extend_from_slice(&pixels);
     }
-
     output
 }
 
+/// Snake-delta encoding, the inverse of [`restore_pixels`]: channel by
+/// channel, emit the wrapping difference to the previous byte on the path.
+fn scramble_pixels(input: &[u8], output: &mut Vec<u8>, width: usize, height: usize, bpp: usize) {
+    let stride = width * bpp;
+    for ch in 0..bpp {
+        let mut prev: u8 = 0;
+        for y in 0..height {
+            let row = &input[y * stride..(y + 1) * stride];
+            let mut emit = |x: usize| {
+                let v = row[x * bpp + ch];
+                output.push(v.wrapping_sub(prev));
+                prev = v;
+            };
+            if y % 2 == 0 {
+                (0..width).for_each(&mut emit);
+            } else {
+                (0..width).rev().for_each(&mut emit);
+            }
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // RestorePixels (snake-delta decoding, ported from GARBro's ImageBGI.cs)
 // ---------------------------------------------------------------------------
@@ -204,7 +240,7 @@ mod tests {
             })
             .collect();
 
-        let encoded = encode_bgi(&rgba, width, height, false);
+        let encoded = encode_bgi(&rgba, width, height, PixelLayout::Bgr);
         assert!(is_bgi(&encoded));
 
         let (decoded, dw, dh) = decrypt_bgi(&encoded).unwrap();
@@ -231,8 +267,61 @@ mod tests {
             })
             .collect();
 
-        let encoded = encode_bgi(&rgba, width, height, true);
+        let encoded = encode_bgi(&rgba, width, height, PixelLayout::Bgra);
         let (decoded, _, _) = decrypt_bgi(&encoded).unwrap();
         assert_eq!(decoded, rgba);
     }
+
+    #[test]
+    fn test_bgi_scrambled_round_trip() {
+        // Odd height ends the snake on a forward row.
+        let (width, height) = (9u16, 7u16);
+        let total = usize::from(width) * usize::from(height);
+        let rgba: Vec<u8> = (0..total)
+            .flat_map(|i| [(i * 5) as u8, (i / 9 * 30) as u8, 0x44, (i * 11) as u8])
+            .collect();
+
+        for layout in [PixelLayout::Bgr, PixelLayout::Bgra] {
+            let encoded = encode_bgi_scrambled(&rgba, width, height, layout);
+            assert!(is_bgi(&encoded));
+            assert_eq!(encoded[6], 1);
+            assert_eq!(
+                encoded.len(),
+                encode_bgi(&rgba, width, height, layout).len()
+            );
+
+            let (decoded, _, _) = decrypt_bgi(&encoded).unwrap();
+            let expected: Vec<u8> = if layout == PixelLayout::Bgra {
+                rgba.clone()
+            } else {
+                rgba.chunks_exact(4)
+                    .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                    .collect()
+            };
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn test_bgi_grayscale_round_trip() {
+        let (width, height) = (10u16, 5u16);
+        let rgba: Vec<u8> = (0..50u8)
+            .flat_map(|v| [v * 5, v * 5, v * 5, 0xFF])
+            .collect();
+        assert_eq!(PixelLayout::detect(&rgba), PixelLayout::Gray);
+
+        for scrambled in [false, true] {
+            let encoded = if scrambled {
+                encode_bgi_scrambled(&rgba, width, height, PixelLayout::Gray)
+            } else {
+                encode_bgi(&rgba, width, height, PixelLayout::Gray)
+            };
+            assert!(is_bgi(&encoded));
+            assert_eq!(encoded[4], 8);
+            assert_eq!(encoded.len(), 0x10 + 50);
+
+            let (decoded, _, _) = decrypt_bgi(&encoded).unwrap();
+            assert_eq!(decoded, rgba);
+        }
+    }
 }
@@ -0,0 +1,45 @@
+//! Bit-level output shared by the encoders.
+
+// Bytes are taken from the low bits of the cache.
+#![allow(clippy::cast_possible_truncation)]
+
+/// MSB-first bit writer — the inverse of the CBG bit readers, shared by the
+/// CBG and DSC encoders.
+pub(crate) struct MsbBitWriter {
+    data: Vec<u8>,
+    cache: u32,
+    cache_size: u32,
+}
+
+impl MsbBitWriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            cache: 0,
+            cache_size: 0,
+        }
+    }
+
+    /// Write the low `len` bits of `code`, most-significant bit first.
+    pub(crate) fn write_bits(&mut self, code: u32, len: u32) {
+        for i in (0..len).rev() {
+            let bit = (code >> i) & 1;
+            self.cache = (self.cache << 1) | bit;
+            self.cache_size += 1;
+            if self.cache_size == 8 {
+                self.data.push(self.cache as u8);
+                self.cache = 0;
+                self.cache_size = 0;
+            }
+        }
+    }
+
+    /// Flush any remaining partial byte (zero-padded on the right).
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.cache_size > 0 {
+            self.cache <<= 8 - self.cache_size;
+            self.data.push(self.cache as u8);
+        }
+        self.data
+    }
+}
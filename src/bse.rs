@@ -1,4 +1,4 @@
-//! BSE (`BuriKo` Stream Encryption) decryption.
+//! BSE (`BuriKo` Stream Encryption) encryption and decryption.
 //!
 //! Supports BSE 1.0 and BSE 1.1, matching `GARBro`'s ArcBGI.cs implementation.
 
@@ -10,10 +10,105 @@
     clippy::unreadable_literal
 )]
 
+use std::fmt;
+
 use log::debug;
 
 use crate::error::{ArcError, ArcResult};
 
+/// BSE format revision; selects the key generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BseVersion {
+    /// BSE 1.0 (`BseGenerator100`).
+    #[default]
+    V100,
+    /// BSE 1.1 (`BseGenerator101`).
+    V101,
+}
+
+impl BseVersion {
+    /// Version field as stored at offset 0x08.
+    #[must_use]
+    pub fn raw(self) -> u16 {
+        match self {
+            Self::V100 => 0x100,
+            Self::V101 => 0x101,
+        }
+    }
+}
+
+impl fmt::Display for BseVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V100 => write!(f, "BSE 1.0"),
+            Self::V101 => write!(f, "BSE 1.1"),
+        }
+    }
+}
+
+impl TryFrom<&str> for BseVersion {
+    type Error = ArcError;
+
+    fn try_from(v: &str) -> Result<Self, Self::Error> {
+        match v {
+            "1.0" | "100" => Ok(Self::V100),
+            "1.1" | "101" => Ok(Self::V101),
+            _ => Err(ArcError::InvalidBseVersion(v.to_owned())),
+        }
+    }
+}
+
+/// Entries whose name matches `pattern` are BSE-wrapped when packing.
+///
+/// The pattern is matched against the whole entry name; `*` matches any run
+/// of characters and `?` a single one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BseRule {
+    pub pattern: String,
+    pub version: BseVersion,
+}
+
+impl BseRule {
+    #[must_use]
+    pub fn new(pattern: impl Into<String>, version: BseVersion) -> Self {
+        Self {
+            pattern: pattern.into(),
+            version,
+        }
+    }
+
+    /// Whether `name` matches this rule's pattern.
+    #[must_use]
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<char> = self.pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        // Greedy wildcard matching, backtracking to the last `*`.
+        let (mut p, mut n) = (0, 0);
+        let mut star = None;
+        while n < name.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match star {
+                    Some((sp, sn)) => {
+                        star = Some((sp, sn + 1));
+                        p = sp + 1;
+                        n = sn + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
 /// Check whether the data starts with a valid BSE 1.x signature.
 #[must_use]
 pub fn is_bse(data: &[u8]) -> bool {
@@ -81,6 +176,70 @@ pub fn decrypt_bse(data: &mut [u8]) -> ArcResult<()> {
     }
 }
 
+/// Decrypt a BSE file and strip its 0x10-byte metadata, returning the inner
+/// file: the decrypted 0x40-byte header followed by the plaintext body.
+pub fn strip(data: &[u8]) -> ArcResult<Vec<u8>> {
+    let mut payload = data.to_vec();
+    decrypt_bse(&mut payload)?;
+    payload.drain(..0x10);
+    Ok(payload)
+}
+
+/// Wrap `data` in BSE: a 0x10-byte metadata block (signature, version,
+/// checksums and `key`), followed by `data` with its first 0x40 bytes
+/// scrambled. Inverse of [`strip`].
+///
+/// Fails if `data` is shorter than the 0x40-byte encrypted header.
+pub fn encrypt_bse(data: &[u8], version: BseVersion, key: u32) -> ArcResult<Vec<u8>> {
+    if data.len() < 0x40 {
+        return Err(ArcError::BseTooShort(data.len()));
+    }
+    let raw_version = version.raw();
+
+    let mut output = Vec::with_capacity(0x10 + data.len());
+    output.extend_from_slice(b"BSE 1.");
+    output.push(b'0' + (raw_version & 0xFF) as u8);
+    output.push(0);
+    output.extend_from_slice(&raw_version.to_le_bytes());
+
+    let (mut sum, mut xor) = (0u8, 0u8);
+    for &b in &data[..0x40] {
+        sum = sum.wrapping_add(b);
+        xor ^= b;
+    }
+    output.push(sum);
+    output.push(xor);
+    output.extend_from_slice(&key.to_le_bytes());
+    output.extend_from_slice(data);
+
+    // Walk the same key sequence as `decrypt_bse`, applying each step's
+    // inverse.
+    let mut flags = [false; 0x40];
+    let mut hash = key as i32;
+    for _ in 0..0x40 {
+        let rand1 = bse_next_key(&mut hash, raw_version);
+        let mut dst = (rand1 & 0x3F) as usize;
+        while flags[dst] {
+            dst = (dst + 1) & 0x3F;
+        }
+
+        let shift = (bse_next_key(&mut hash, raw_version) & 7) as u32;
+        let right_shift = (bse_next_key(&mut hash, raw_version) & 1) == 0;
+        let plain = output[0x10 + dst];
+        let rotated = if right_shift {
+            rot_byte_l(plain, shift)
+        } else {
+            rot_byte_r(plain, shift)
+        };
+        output[0x10 + dst] = rotated.wrapping_add(bse_next_key(&mut hash, raw_version) as u8);
+
+        flags[dst] = true;
+    }
+
+    debug!("{version}: wrapped {} bytes", data.len());
+    Ok(output)
+}
+
 /// BSE key generator (dispatches to version-specific PRNG).
 fn bse_next_key(seed: &mut i32, version: u16) -> i32 {
     if version == 0x101 {
@@ -134,3 +293,31 @@ fn rot_byte_l(v: u8, count: u32) -> u8 {
     let count = count & 7;
     v.rotate_left(count)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_bse_round_trip() {
+        let data: Vec<u8> = (0..0x60u8).collect();
+        for version in [BseVersion::V100, BseVersion::V101] {
+            let wrapped = encrypt_bse(&data, version, 0x1234_5678).unwrap();
+            assert!(is_bse(&wrapped));
+            assert_ne!(wrapped[0x10..0x50], data[..0x40]);
+            assert_eq!(wrapped[0x50..], data[0x40..]);
+            assert_eq!(strip(&wrapped).unwrap(), data);
+        }
+        assert!(encrypt_bse(&data[..0x3F], BseVersion::V100, 0).is_err());
+    }
+
+    #[test]
+    fn test_bse_rule_matches() {
+        let rule = BseRule::new("ev*_?", BseVersion::V100);
+        assert!(rule.matches("ev01_a"));
+        assert!(rule.matches("ev_b"));
+        assert!(!rule.matches("ev01_ab"));
+        assert!(!rule.matches("bg01_a"));
+        assert!(BseRule::new("*", BseVersion::V100).matches(""));
+    }
+}
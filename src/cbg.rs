@@ -1,7 +1,8 @@
-//! `CompressedBG` (CBG) image format decoder.
+//! `CompressedBG` (CBG) image format decoder and encoder.
 //!
 //! Supports both V1 (Huffman + delta prediction) and V2 (DCT + Huffman)
-//! decoding, ported from `GARBro`'s ImageCBG.cs implementation.
+//! decoding, ported from `GARBro`'s ImageCBG.cs implementation, and encodes
+//! both versions.
 
 // This module is ported from C# (GARBro). Integer casts, magic constants and
 // verbose variable names mirror the original implementation for auditability.
@@ -25,9 +26,10 @@ use log::debug;
 use rayon::prelude::*;
 
 use crate::{
-    decrypt::hash_update,
+    bits::MsbBitWriter,
+    decrypt::{content_key, hash_update},
     error::{ArcError, ArcResult},
-    write::{convert_bgr_to_rgba, write_rgba_to_png},
+    write::{PixelLayout, convert_bgr_to_rgba, write_rgba_to_png},
 };
 
 // Type aliases for better readability
@@ -45,6 +47,9 @@ pub fn is_cbg(data: &[u8]) -> bool {
 
 /// Decrypt a `CompressedBG` buffer, returning (RGBA pixels, width, height).
 pub fn decrypt_cbg(crypted: &[u8]) -> ArcResult<(Vec<u8>, u16, u16)> {
+    if crypted.len() < 0x30 {
+        return Err(ArcError::CbgDecryptError);
+    }
     let mut ptr = &crypted[16..];
 
     let width = ptr.get_u16_le();
@@ -117,6 +122,10 @@ fn decrypt_v1(
     let tree = HuffmanTree::new_v1(&weights);
 
     // --- Step 3: Huffman-decompress into intermediate buffer ---
+    // Every code takes at least one bit of the remaining input.
+    if intermediate_length as usize > ptr.len() * 8 {
+        return Err(ArcError::CbgDecryptError);
+    }
     let mut intermediate = vec![0u8; intermediate_length as usize];
     let mut bits = MsbBitStream::new(ptr);
     for dst in &mut intermediate {
@@ -124,8 +133,16 @@ fn decrypt_v1(
     }
 
     // --- Step 4: Unpack zeros (variable-length run-length encoding) ---
+    if !matches!(bpp, 8 | 16 | 24 | 32) {
+        return Err(ArcError::CbgDecryptError);
+    }
     let pixel_size = (bpp / 8) as usize;
     let stride = width as usize * pixel_size;
+    // The runs must cover the whole image, so a corrupt size in the header
+    // cannot allocate more than the input describes.
+    if unpacked_len(&intermediate) < stride * height as usize {
+        return Err(ArcError::CbgDecryptError);
+    }
     let mut output = vec![0u8; stride * height as usize];
     unpack_zeros(&intermediate, &mut output);
 
@@ -146,6 +163,9 @@ fn read_encoded_block(
     check_sum: u8,
     check_xor: u8,
 ) -> ArcResult<Vec<u8>> {
+    if ptr.len() < enc_length as usize {
+        return Err(ArcError::CbgDecryptError);
+    }
     let src = ptr[..enc_length as usize].as_ref();
     *ptr = &ptr[enc_length as usize..];
 
@@ -199,6 +219,26 @@ fn unpack_zeros(input: &[u8], output: &mut [u8]) {
     }
 }
 
+/// Number of output bytes [`unpack_zeros`] can fill from `input`.
+fn unpacked_len(input: &[u8]) -> usize {
+    let mut src = 0usize;
+    let mut len = 0usize;
+    let mut dec_zero = false;
+
+    while let Some(count) = read_variable_from_slice(input, &mut src) {
+        let count = count as usize;
+        if !dec_zero {
+            if src + count > input.len() {
+                break;
+            }
+            src += count;
+        }
+        len = len.saturating_add(count);
+        dec_zero = !dec_zero;
+    }
+    len
+}
+
 /// Reverse the average-prediction encoding, channel by channel.
 /// Ported from `GARBro`'s `ReverseAverageSampling`.
 fn reverse_average_sampling(output: &mut [u8], width: usize, height: usize, pixel_size: usize) {
@@ -240,7 +280,7 @@ fn decrypt_v2(
     check_sum: u8,
     check_xor: u8,
 ) -> ArcResult<(Vec<u8>, u16, u16)> {
-    if enc_length < 0x80 {
+    if enc_length < 0x80 || width == 0 || height == 0 {
         return Err(ArcError::CbgDecryptError);
     }
 
@@ -287,6 +327,9 @@ fn decrypt_v2(
     let current_pos = ptr.as_ptr() as usize - crypted.as_ptr() as usize;
     let input_base = (current_pos + offsets_byte_count - base_offset) as isize;
 
+    if ptr.len() < offsets_byte_count {
+        return Err(ArcError::CbgDecryptError);
+    }
     let mut offsets = Vec::with_capacity(y_blocks + 1);
     for _ in 0..=y_blocks {
         let off = ptr.get_u32_le() as isize;
@@ -323,16 +366,16 @@ fn decrypt_v2(
     output
         .par_chunks_mut(block_row_bytes)
         .enumerate()
-        .for_each(|(i, chunk)| {
+        .try_for_each(|(i, chunk)| {
             let &(block_offset, block_len) = &block_params[i];
             if block_offset >= remaining_data.len() || block_len == 0 {
-                return;
+                return Ok(());
             }
             let end = (block_offset + block_len).min(remaining_data.len());
             let block_data = &remaining_data[block_offset..end];
 
-            decode_block(block_data, &tree1, &tree2, w_align, bpp, &dct, chunk, 0);
-        });
+            decode_block(block_data, &tree1, &tree2, w_align, bpp, &dct, chunk, 0)
+        })?;
 
     // --- Step 7: Decode alpha channel if 32bpp ---
     let has_alpha = if bpp == 32 && !offsets.is_empty() {
@@ -368,12 +411,12 @@ fn decode_block(
     dct: &DctCoefficients,
     output: &mut [u8],
     dst_start: usize,
-) {
+) -> ArcResult<()> {
     let mut bits = MsbBitStreamCursor::new(data);
 
     let block_size = match bits.read_variable() {
         Some(v) => v as usize,
-        None => return,
+        None => return Ok(()),
     };
 
     let block_count = width / 8;
@@ -383,7 +426,10 @@ fn decode_block(
     } else {
         block_count * 64 * 3
     };
-    let mut color_data = vec![0i16; color_data_size.max(block_size)];
+    if block_size > color_data_size {
+        return Err(ArcError::CbgDecryptError);
+    }
+    let mut color_data = vec![0i16; color_data_size];
 
     // --- DC coefficients (Tree1) ---
     let mut acc: i32 = 0;
@@ -446,6 +492,7 @@ fn decode_block(
     } else {
         decode_rgb(&color_data, width, dct, output, dst_start);
     }
+    Ok(())
 }
 
 /// Decode RGB blocks (24/32 bpp): 3-channel DCT + YCbCr->RGB conversion.
@@ -794,7 +841,7 @@ impl HuffmanTree {
                 left: usize::MAX,
                 right: usize::MAX,
             });
-            root_weight += w;
+            root_weight = root_weight.saturating_add(w);
         }
 
         if root_weight == 0 {
@@ -834,7 +881,7 @@ impl HuffmanTree {
 
                 if child_idx[i] != usize::MAX {
                     nodes[child_idx[i]].valid = false;
-                    total_weight += nodes[child_idx[i]].weight;
+                    total_weight = total_weight.saturating_add(nodes[child_idx[i]].weight);
                 }
             }
 
@@ -989,8 +1036,8 @@ impl<'a> MsbBitStreamCursor<'a> {
         let mut shift = 0;
         loop {
             let byte = self.read_byte()?;
-            v |= u32::from(byte & 0x7F) << shift;
-            shift += 7;
+            v |= u32::from(byte & 0x7F).checked_shl(shift).unwrap_or(0);
+            shift = shift.saturating_add(7);
             if byte & 0x80 == 0 {
                 return Some(v);
             }
@@ -1056,8 +1103,8 @@ fn read_variable(ptr: &mut &[u8]) -> u32 {
         }
         let c = ptr[0];
         *ptr = &ptr[1..];
-        v |= u32::from(c & 0x7F) << shift;
-        shift += 7;
+        v |= u32::from(c & 0x7F).checked_shl(shift).unwrap_or(0);
+        shift = shift.saturating_add(7);
         if c & 0x80 == 0 {
             break;
         }
@@ -1077,8 +1124,8 @@ fn read_variable_from_slice(data: &[u8], pos: &mut usize) -> Option<u32> {
         }
         let c = data[*pos];
         *pos += 1;
-        v |= u32::from(c & 0x7F) << shift;
-        shift += 7;
+        v |= u32::from(c & 0x7F).checked_shl(shift).unwrap_or(0);
+        shift = shift.saturating_add(7);
         if c & 0x80 == 0 {
             return Some(v);
         }
@@ -1145,44 +1192,69 @@ const BLOCK_FILL_ORDER: [usize; 64] = [
 // Encoding (PNG → CompressedBG V1)
 // ===========================================================================
 
-/// Encode RGBA pixels into a `CompressedBG` V1 image buffer.
+/// Encode RGBA pixels into a `CompressedBG` V1 image buffer, with a key
+/// derived from the pixels.
 ///
 /// Pipeline (inverse of [`decrypt_v1`]):
-/// 1. RGBA → BGR(A) raw pixels
+/// 1. RGBA → grey, BGR or BGRA raw pixels, as chosen by `layout`
 /// 2. Forward average sampling (subtract predicted neighbor average)
-/// 3. Pack into literal/zero-run segments (simple all-literal variant)
+/// 3. Pack into alternating literal / zero-run segments
 /// 4. Huffman-compress using byte frequencies as weights
 /// 5. Encrypt the weight table with the BGI key stream
-pub fn encode_cbg_v1(rgba: &[u8], width: u16, height: u16, has_alpha: bool) -> ArcResult<Vec<u8>> {
+pub fn encode_cbg_v1(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    layout: PixelLayout,
+) -> ArcResult<Vec<u8>> {
+    encode_cbg_v1_with_key(rgba, width, height, layout, content_key(rgba))
+}
+
+/// Like [`encode_cbg_v1`], encrypting the weight table with `key`.
+pub fn encode_cbg_v1_with_key(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    layout: PixelLayout,
+    key: u32,
+) -> ArcResult<Vec<u8>> {
     if width == 0 || height == 0 {
         return Err(ArcError::CbgDecryptError);
     }
 
-    let bpp: u32 = if has_alpha { 32 } else { 24 };
-    let pixel_size = (bpp / 8) as usize;
+    let bpp = u32::from(layout.bpp());
+    let pixel_size = layout.pixel_size();
     let w = width as usize;
     let h = height as usize;
 
-    // --- Step 1: RGBA → BGR(A) ---
-    let raw = rgba_to_bgr(rgba, w, h, pixel_size);
+    // --- Step 1: RGBA → stored pixel layout ---
+    let raw = layout.pack(rgba);
 
     // --- Step 2: Forward average sampling ---
     let residual = forward_average_sampling(&raw, w, h, pixel_size);
 
-    // --- Step 3: Pack zeros (all-literal: single literal run covering everything)
-    // ---
-    let mut intermediate = Vec::with_capacity(5 + residual.len());
-    write_variable(&mut intermediate, residual.len() as u32);
-    intermediate.extend_from_slice(&residual);
+    // --- Step 3: Pack zeros, with the run threshold that compresses best ---
+    let (intermediate, freq, codes) = ZERO_RUN_THRESHOLDS
+        .iter()
+        .map(|&min_run| {
+            let packed = pack_zeros(&residual, min_run);
+            let mut freq = [0u32; 256];
+            for &b in &packed {
+                freq[b as usize] += 1;
+            }
+            let codes = HuffmanTree::new_v1(&freq).generate_codes();
+            (packed, freq, codes)
+        })
+        .min_by_key(|(_, freq, codes)| {
+            freq.iter()
+                .zip(codes)
+                .map(|(&f, &(_, len))| u64::from(f) * u64::from(len))
+                .sum::<u64>()
+        })
+        .expect("at least one threshold");
     let intermediate_length = intermediate.len() as u32;
 
     // --- Step 4: Huffman-compress ---
-    let mut freq = [0u32; 256];
-    for &b in &intermediate {
-        freq[b as usize] += 1;
-    }
-    let tree = HuffmanTree::new_v1(&freq);
-    let codes = tree.generate_codes();
 
     // Guard against pathological code lengths that overflow u32.
     if freq
@@ -1206,7 +1278,6 @@ pub fn encode_cbg_v1(rgba: &[u8], width: u16, height: u16, has_alpha: bool) -> A
         write_variable(&mut weight_bytes, w);
     }
 
-    let key = 0u32;
     let (enc_data, check_sum, check_xor) = encrypt_block(&weight_bytes, key);
     let enc_length = enc_data.len() as u32;
 
@@ -1235,19 +1306,34 @@ pub fn encode_cbg_v1(rgba: &[u8], width: u16, height: u16, has_alpha: bool) -> A
 
 // --- Encoding helpers ---
 
-/// Convert RGBA pixels to interleaved BGR(A) byte order.
-fn rgba_to_bgr(rgba: &[u8], width: usize, height: usize, pixel_size: usize) -> Vec<u8> {
-    let total = width * height;
-    let mut out = vec![0u8; total * pixel_size];
-    for i in 0..total {
-        let src = i * 4;
-        let dst = i * pixel_size;
-        out[dst] = rgba[src + 2]; // B
-        out[dst + 1] = rgba[src + 1]; // G
-        out[dst + 2] = rgba[src]; // R
-        if pixel_size == 4 {
-            out[dst + 3] = rgba[src + 3]; // A
+/// Zero-run lengths tried by [`encode_cbg_v1`] as the shortest run worth its
+/// own segment; each segment boundary costs two variable-length counts.
+const ZERO_RUN_THRESHOLDS: [usize; 5] = [2, 3, 4, 6, 8];
+
+/// Split residuals into the alternating literal / zero-run segments read by
+/// [`unpack_zeros`], starting with a literal run. Zero runs shorter than
+/// `min_run` stay inside the surrounding literal run.
+fn pack_zeros(residual: &[u8], min_run: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(residual.len() + 16);
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos < residual.len() {
+        if residual[pos] != 0 {
+            pos += 1;
+            continue;
         }
+        let run = residual[pos..].iter().take_while(|&&b| b == 0).count();
+        if run >= min_run {
+            write_variable(&mut out, (pos - literal_start) as u32);
+            out.extend_from_slice(&residual[literal_start..pos]);
+            write_variable(&mut out, run as u32);
+            literal_start = pos + run;
+        }
+        pos += run;
+    }
+    if literal_start < residual.len() {
+        write_variable(&mut out, (residual.len() - literal_start) as u32);
+        out.extend_from_slice(&residual[literal_start..]);
     }
     out
 }
@@ -1327,47 +1413,419 @@ fn encrypt_block(plain: &[u8], key_init: u32) -> (Vec<u8>, u8, u8) {
     (cipher, sum, xor)
 }
 
-/// MSB-first bit writer — the inverse of [`MsbBitStream`] /
-/// [`MsbBitStreamCursor`].
-struct MsbBitWriter {
-    data: Vec<u8>,
-    cache: u32,
-    cache_size: u32,
+// ===========================================================================
+// Encoding (PNG → CompressedBG V2)
+// ===========================================================================
+
+/// Quality used by [`encode_cbg_v2`] callers that do not pick one.
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// Standard JPEG luminance quantization table (natural order), quality 50.
+const LUMA_QUANT: [u32; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Standard JPEG chrominance quantization table (natural order), quality 50.
+const CHROMA_QUANT: [u32; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Largest AC magnitude tree 2 can express (10-bit size class).
+const MAX_AC: i32 = 0x3FF;
+
+/// Encode RGBA pixels into a `CompressedBG` V2 image buffer.
+///
+/// Pipeline (inverse of [`decrypt_v2`]):
+/// 1. RGBA → full-resolution YCbCr planes (only Y for an 8 bpp `layout`),
+///    padded to 8×8 blocks
+/// 2. Forward DCT per block, quantized with JPEG tables scaled by `quality`
+///    (1–100, libjpeg scaling); the tables are stored encrypted
+/// 3. DC deltas (tree 1) and zigzag run/size AC symbols (tree 2), Huffman
+///    coded per row of blocks, with a table of row-block offsets
+/// 4. For 32 bpp, the alpha plane as a literal/back-reference stream
+///
+/// The colour planes are lossy; alpha is stored losslessly.
+pub fn encode_cbg_v2(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    layout: PixelLayout,
+    quality: u8,
+) -> ArcResult<Vec<u8>> {
+    if width == 0 || height == 0 {
+        return Err(ArcError::CbgDecryptError);
+    }
+
+    let bpp = u32::from(layout.bpp());
+    let w = width as usize;
+    let h = height as usize;
+    let w_align = (w + 7) & !7;
+    let h_align = (h + 7) & !7;
+    let y_blocks = h_align / 8;
+    let pad_skip = ((w_align >> 3) + 7) >> 3;
+
+    // --- Step 1: quantization tables ---
+    let quant = [
+        scale_quant(&LUMA_QUANT, quality),
+        scale_quant(&CHROMA_QUANT, quality),
+    ];
+    let mut quant_bytes = Vec::with_capacity(0x80);
+    quant_bytes.extend_from_slice(&quant[0]);
+    quant_bytes.extend_from_slice(&quant[1]);
+
+    // --- Step 2: colour planes → quantized coefficients per row-block ---
+    let mut planes = Vec::from(ycbcr_planes(rgba, w, h, w_align, h_align));
+    if layout == PixelLayout::Gray {
+        // The 8 bpp decoder reads a single luma channel per block.
+        planes.truncate(1);
+    }
+    let basis = dct_basis();
+    let rows: Vec<Vec<i16>> = (0..y_blocks)
+        .into_par_iter()
+        .map(|by| quantize_row(&planes, w_align, by, &quant, &basis))
+        .collect();
+    let symbols: Vec<RowSymbols> = rows.par_iter().map(|row| row_symbols(row)).collect();
+
+    // --- Step 3: Huffman trees shared by all row-blocks ---
+    let mut dc_freq = [0u32; 0x10];
+    let mut ac_freq = [0u32; 0xB0];
+    for row in &symbols {
+        for &(sym, _, _) in &row.dc {
+            dc_freq[sym as usize] += 1;
+        }
+        for &(sym, _, _) in &row.ac {
+            ac_freq[sym as usize] += 1;
+        }
+    }
+    let (dc_freq, dc_codes) = limited_codes(&dc_freq);
+    let (ac_freq, ac_codes) = limited_codes(&ac_freq);
+
+    let block_size = (w_align / 8 * 64 * planes.len()) as u32;
+    let encoded_rows: Vec<Vec<u8>> = symbols
+        .par_iter()
+        .map(|row| {
+            let mut out = vec![0u8; pad_skip];
+            write_variable(&mut out, block_size);
+            for (entries, codes) in [(&row.dc, &dc_codes), (&row.ac, &ac_codes)] {
+                let mut writer = MsbBitWriter::new();
+                for &(sym, bits, len) in entries {
+                    let (code, code_len) = codes[sym as usize];
+                    writer.write_bits(code, code_len);
+                    writer.write_bits(bits, len);
+                }
+                // The decoder realigns to a byte boundary after the DC pass.
+                out.extend_from_slice(&writer.finish());
+            }
+            out
+        })
+        .collect();
+
+    // --- Step 4: weight tables, row-block offsets, alpha ---
+    let mut tables = Vec::with_capacity(0xC0 * 2);
+    for &weight in dc_freq.iter().chain(&ac_freq) {
+        write_variable(&mut tables, weight);
+    }
+
+    // Offsets are relative to the start of the weight tables.
+    let input_base = tables.len() + (y_blocks + 1) * 4;
+    let mut offset = input_base;
+    for row in &encoded_rows {
+        tables.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += row.len();
+    }
+    tables.extend_from_slice(&(offset as u32).to_le_bytes());
+
+    let alpha = if layout == PixelLayout::Bgra {
+        encode_alpha(&alpha_plane(rgba, w, h, w_align, h_align), w_align)
+    } else {
+        Vec::new()
+    };
+
+    let key = 0u32;
+    let (enc_data, check_sum, check_xor) = encrypt_block(&quant_bytes, key);
+
+    // --- Assemble the CBG V2 file ---
+    let body_len = tables.len() + encoded_rows.iter().map(Vec::len).sum::<usize>() + alpha.len();
+    let mut output = Vec::with_capacity(0x30 + enc_data.len() + body_len);
+    output.extend_from_slice(b"CompressedBG___");
+    output.push(0);
+    output.extend_from_slice(&width.to_le_bytes());
+    output.extend_from_slice(&height.to_le_bytes());
+    output.extend_from_slice(&bpp.to_le_bytes());
+    output.extend_from_slice(&0u32.to_le_bytes()); // reserved
+    output.extend_from_slice(&0u32.to_le_bytes()); // reserved
+    output.extend_from_slice(&0u32.to_le_bytes()); // intermediate length (V1 only)
+    output.extend_from_slice(&key.to_le_bytes());
+    output.extend_from_slice(&(enc_data.len() as u32).to_le_bytes());
+    output.push(check_sum);
+    output.push(check_xor);
+    output.extend_from_slice(&2u16.to_le_bytes()); // version = 2
+    output.extend_from_slice(&enc_data);
+    output.extend_from_slice(&tables);
+    for row in &encoded_rows {
+        output.extend_from_slice(row);
+    }
+    output.extend_from_slice(&alpha);
+
+    Ok(output)
 }
 
-impl MsbBitWriter {
-    fn new() -> Self {
-        Self {
-            data: Vec::new(),
-            cache: 0,
-            cache_size: 0,
+// --- V2 encoding helpers ---
+
+/// Scale a quality-50 JPEG table to `quality` the way libjpeg does.
+fn scale_quant(base: &[u32; 64], quality: u8) -> [u8; 64] {
+    let quality = u32::from(quality.clamp(1, 100));
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    base.map(|q| ((q * scale + 50) / 100).clamp(1, 255) as u8)
+}
+
+/// `basis[u][x] = C(u) / 2 · cos((2x + 1)uπ / 16)`, the orthonormal 1-D DCT-II
+/// that [`decode_dct`]'s scaled AAN transform inverts.
+fn dct_basis() -> [[f32; 8]; 8] {
+    let mut basis = [[0.0f32; 8]; 8];
+    for (u, row) in basis.iter_mut().enumerate() {
+        let c = if u == 0 {
+            std::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        };
+        for (x, v) in row.iter_mut().enumerate() {
+            let angle = f32::from(((2 * x + 1) * u) as u16) * std::f32::consts::PI / 16.0;
+            *v = c / 2.0 * angle.cos();
         }
     }
+    basis
+}
 
-    /// Write the low `len` bits of `code`, most-significant bit first.
-    fn write_bits(&mut self, code: u32, len: u32) {
-        for i in (0..len).rev() {
-            let bit = (code >> i) & 1;
-            self.cache = (self.cache << 1) | bit;
-            self.cache_size += 1;
-            if self.cache_size == 8 {
-                self.data.push(self.cache as u8);
-                self.cache = 0;
-                self.cache_size = 0;
+/// Full-resolution Y, Cb and Cr planes of `w_align × h_align` samples,
+/// level-shifted for the DCT, with edge pixels repeated into the padding.
+///
+/// Samples are shifted by 127.5 rather than 128 so that the decoder's
+/// truncating descale rounds to the nearest value.
+fn ycbcr_planes(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    w_align: usize,
+    h_align: usize,
+) -> [Vec<f32>; 3] {
+    let mut planes = [
+        vec![0.0f32; w_align * h_align],
+        vec![0.0f32; w_align * h_align],
+        vec![0.0f32; w_align * h_align],
+    ];
+    for y in 0..h_align {
+        let sy = y.min(height - 1);
+        for x in 0..w_align {
+            let src = (sy * width + x.min(width - 1)) * 4;
+            let r = f32::from(rgba[src]);
+            let g = f32::from(rgba[src + 1]);
+            let b = f32::from(rgba[src + 2]);
+            let dst = y * w_align + x;
+            planes[0][dst] = 0.299 * r + 0.587 * g + 0.114 * b - 127.5;
+            planes[1][dst] = -0.168736 * r - 0.331264 * g + 0.5 * b + 0.5;
+            planes[2][dst] = 0.5 * r - 0.418688 * g - 0.081312 * b + 0.5;
+        }
+    }
+    planes
+}
+
+/// Forward DCT and quantization of row-block `by`, laid out the way
+/// [`decode_block`] fills `color_data`: every Y block, then Cb, then Cr (if
+/// present), each 8×8 block in natural order.
+fn quantize_row(
+    planes: &[Vec<f32>],
+    w_align: usize,
+    by: usize,
+    quant: &[[u8; 64]; 2],
+    basis: &[[f32; 8]; 8],
+) -> Vec<i16> {
+    let block_count = w_align / 8;
+    let mut coefs = vec![0i16; block_count * 64 * planes.len()];
+    let mut samples = [[0.0f32; 8]; 8];
+    let mut tmp = [[0.0f32; 8]; 8];
+
+    for (channel, plane) in planes.iter().enumerate() {
+        let q = &quant[usize::from(channel > 0)];
+        for blk in 0..block_count {
+            for y in 0..8 {
+                let row = (by * 8 + y) * w_align + blk * 8;
+                samples[y].copy_from_slice(&plane[row..row + 8]);
+            }
+            // Columns, then rows.
+            for v in 0..8 {
+                for x in 0..8 {
+                    tmp[v][x] = (0..8).map(|y| basis[v][y] * samples[y][x]).sum();
+                }
+            }
+            let out = &mut coefs[(channel * block_count + blk) * 64..][..64];
+            for v in 0..8 {
+                for u in 0..8 {
+                    let f: f32 = (0..8).map(|x| basis[u][x] * tmp[v][x]).sum();
+                    let k = v * 8 + u;
+                    let level = (f / f32::from(q[k])).round() as i32;
+                    out[k] = level.clamp(-MAX_AC, MAX_AC) as i16;
+                }
             }
         }
     }
+    coefs
+}
 
-    /// Flush any remaining partial byte (zero-padded on the right).
-    fn finish(mut self) -> Vec<u8> {
-        if self.cache_size > 0 {
-            self.cache <<= 8 - self.cache_size;
-            self.data.push(self.cache as u8);
+/// Huffman symbols of one row-block: `(symbol, extra bits, extra bit count)`.
+struct RowSymbols {
+    /// DC size classes (tree 1).
+    dc: Vec<(u8, u32, u32)>,
+    /// AC run/size symbols (tree 2): `size << 4 | run`, `0x0F` for a run of
+    /// sixteen zeros and `0x00` for end of block.
+    ac: Vec<(u8, u32, u32)>,
+}
+
+fn row_symbols(coefs: &[i16]) -> RowSymbols {
+    let blocks = coefs.len() / 64;
+    let mut symbols = RowSymbols {
+        dc: Vec::with_capacity(blocks),
+        ac: Vec::with_capacity(blocks * 8),
+    };
+
+    let mut prev = 0i32;
+    for block in coefs.chunks_exact(64) {
+        let dc = i32::from(block[0]);
+        let (len, bits) = magnitude(dc - prev);
+        symbols.dc.push((len as u8, bits, len));
+        prev = dc;
+
+        let last = (1..64)
+            .rev()
+            .find(|&k| block[BLOCK_FILL_ORDER[k]] != 0)
+            .unwrap_or(0);
+        let mut run = 0u32;
+        for k in 1..=last {
+            let v = i32::from(block[BLOCK_FILL_ORDER[k]]);
+            if v == 0 {
+                run += 1;
+                continue;
+            }
+            while run >= 16 {
+                symbols.ac.push((0x0F, 0, 0));
+                run -= 16;
+            }
+            let (len, bits) = magnitude(v);
+            symbols.ac.push(((len << 4 | run) as u8, bits, len));
+            run = 0;
+        }
+        if last < 63 {
+            symbols.ac.push((0, 0, 0));
+        }
+    }
+    symbols
+}
+
+/// JPEG-style size class and extra bits of a signed value: negative values
+/// are stored as `v + 2^len - 1`.
+fn magnitude(v: i32) -> (u32, u32) {
+    let len = 32 - v.unsigned_abs().leading_zeros();
+    let bits = if v < 0 { v + (1 << len) - 1 } else { v };
+    (len, bits as u32)
+}
+
+/// Build a V2 Huffman tree from `freq` and return the weights actually used
+/// with their codes. Weights are halved until every code fits in 31 bits.
+fn limited_codes(freq: &[u32]) -> (Vec<u32>, [(u32, u32); 256]) {
+    let mut weights = freq.to_vec();
+    loop {
+        let codes = HuffmanTree::new_v2(&weights).generate_codes();
+        if codes.iter().all(|&(_, len)| len < 32) {
+            return (weights, codes);
+        }
+        for w in weights.iter_mut().filter(|w| **w > 0) {
+            *w = (*w >> 1).max(1);
         }
-        self.data
     }
 }
 
+/// Alpha channel padded to `w_align × h_align` by repeating edge pixels.
+fn alpha_plane(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    w_align: usize,
+    h_align: usize,
+) -> Vec<u8> {
+    (0..h_align)
+        .flat_map(|y| (0..w_align).map(move |x| (y.min(height - 1), x.min(width - 1))))
+        .map(|(y, x)| rgba[(y * width + x) * 4 + 3])
+        .collect()
+}
+
+/// Encode the alpha plane for [`decode_alpha`]: a `1` flag, then groups of
+/// eight items behind an LSB-first control byte. An item is either a literal
+/// byte or a 3..=130 byte copy from a nearby earlier pixel.
+fn encode_alpha(alpha: &[u8], width: usize) -> Vec<u8> {
+    /// Reference positions tried for copies, as `(x, y)` pixel offsets.
+    const CANDIDATES: [(i32, i32); 8] = [
+        (-1, 0),
+        (-2, 0),
+        (-3, 0),
+        (-4, 0),
+        (0, -1),
+        (-1, -1),
+        (1, -1),
+        (0, -2),
+    ];
+
+    let mut out = 1u32.to_le_bytes().to_vec();
+    let mut ctl_pos = 0;
+    let mut ctl_bit = 8;
+    let mut pos = 0;
+    while pos < alpha.len() {
+        if ctl_bit == 8 {
+            ctl_pos = out.len();
+            out.push(0);
+            ctl_bit = 0;
+        }
+
+        let best = CANDIDATES
+            .iter()
+            .filter_map(|&(x, y)| {
+                let distance = (-(x + y * width as i32)) as usize;
+                if x + y * width as i32 >= 0 || distance > pos {
+                    return None;
+                }
+                let len = (0..130)
+                    .take_while(|&j| {
+                        pos + j < alpha.len() && alpha[pos + j] == alpha[pos + j - distance]
+                    })
+                    .count();
+                Some((len, x, y))
+            })
+            .max_by_key(|&(len, ..)| len);
+
+        match best {
+            Some((len, x, y)) if len >= 3 => {
+                let v = (x & 0x3F) as u16 | (((y & 7) as u16) << 6) | (((len - 3) as u16) << 9);
+                out[ctl_pos] |= 1 << ctl_bit;
+                out.extend_from_slice(&v.to_le_bytes());
+                pos += len;
+            }
+            _ => {
+                out.push(alpha[pos]);
+                pos += 1;
+            }
+        }
+        ctl_bit += 1;
+    }
+    out
+}
+
 #[cfg(test)]
 mod encode_tests {
     use super::*;
@@ -1390,7 +1848,7 @@ mod encode_tests {
             })
             .collect();
 
-        let encoded = encode_cbg_v1(&rgba, width, height, false).unwrap();
+        let encoded = encode_cbg_v1(&rgba, width, height, PixelLayout::Bgr).unwrap();
         assert!(is_cbg(&encoded));
 
         let (decoded, dw, dh) = decrypt_cbg(&encoded).unwrap();
@@ -1416,9 +1874,121 @@ mod encode_tests {
             })
             .collect();
 
-        let encoded = encode_cbg_v1(&rgba, width, height, true).unwrap();
+        let encoded = encode_cbg_v1(&rgba, width, height, PixelLayout::Bgra).unwrap();
+        let (decoded, _, _) = decrypt_cbg(&encoded).unwrap();
+        assert_eq!(decoded, rgba);
+    }
+
+    /// Smooth test pattern that survives DCT quantization well.
+    fn gradient(width: u16, height: u16, alpha: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        let (w, h) = (usize::from(width), usize::from(height));
+        (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                [
+                    (x * 255 / w) as u8,
+                    (y * 255 / h) as u8,
+                    ((x + y) * 127 / (w + h)) as u8 + 64,
+                    alpha(x, y),
+                ]
+            })
+            .collect()
+    }
+
+    fn max_channel_error(a: &[u8], b: &[u8]) -> u8 {
+        a.iter().zip(b).map(|(x, y)| x.abs_diff(*y)).max().unwrap()
+    }
+
+    #[test]
+    fn test_cbg_v2_round_trip() {
+        // Odd dimensions exercise the 8×8 block padding.
+        let (width, height) = (37u16, 21u16);
+        let rgba = gradient(width, height, |_, _| 0xFF);
+
+        let encoded = encode_cbg_v2(&rgba, width, height, PixelLayout::Bgr, 100).unwrap();
+        assert!(is_cbg(&encoded));
+        assert_eq!(u16::from_le_bytes([encoded[0x2E], encoded[0x2F]]), 2);
+
+        let (decoded, dw, dh) = decrypt_cbg(&encoded).unwrap();
+        assert_eq!((dw, dh), (width, height));
+        assert!(max_channel_error(&decoded, &rgba) <= 4);
+
+        let coarse = encode_cbg_v2(&rgba, width, height, PixelLayout::Bgr, 30).unwrap();
+        assert!(coarse.len() < encoded.len());
+        let (decoded, _, _) = decrypt_cbg(&coarse).unwrap();
+        assert!(max_channel_error(&decoded, &rgba) <= 32);
+    }
+
+    #[test]
+    fn test_cbg_v2_round_trip_alpha() {
+        let (width, height) = (24u16, 19u16);
+        let rgba = gradient(width, height, |x, y| match (x / 6 + y / 5) % 3 {
+            0 => 0,
+            1 => 0x80,
+            _ => (x * 10) as u8,
+        });
+
+        let encoded =
+            encode_cbg_v2(&rgba, width, height, PixelLayout::Bgra, DEFAULT_QUALITY).unwrap();
+        let (decoded, _, _) = decrypt_cbg(&encoded).unwrap();
+        let alpha = |px: &[u8]| px.chunks_exact(4).map(|p| p[3]).collect::<Vec<_>>();
+        assert_eq!(alpha(&decoded), alpha(&rgba));
+        assert!(max_channel_error(&decoded, &rgba) <= 16);
+    }
+
+    #[test]
+    fn test_cbg_grayscale_round_trip() {
+        let (width, height) = (19u16, 13u16);
+        let rgba: Vec<u8> = gradient(width, height, |_, _| 0xFF)
+            .chunks_exact(4)
+            .flat_map(|px| [px[0], px[0], px[0], 0xFF])
+            .collect();
+        let bpp = |data: &[u8]| u32::from_le_bytes(data[0x14..0x18].try_into().unwrap());
+
+        let v1 = encode_cbg_v1(&rgba, width, height, PixelLayout::Gray).unwrap();
+        assert_eq!(bpp(&v1), 8);
+        assert!(
+            v1.len()
+                < encode_cbg_v1(&rgba, width, height, PixelLayout::Bgr)
+                    .unwrap()
+                    .len()
+        );
+        assert_eq!(decrypt_cbg(&v1).unwrap().0, rgba);
+
+        let v2 = encode_cbg_v2(&rgba, width, height, PixelLayout::Gray, 100).unwrap();
+        assert_eq!(bpp(&v2), 8);
+        let (decoded, _, _) = decrypt_cbg(&v2).unwrap();
+        assert!(max_channel_error(&decoded, &rgba) <= 4);
+    }
+
+    #[test]
+    fn test_cbg_v1_zero_runs_and_key() {
+        // Flat areas become long zero runs after average sampling.
+        let (width, height) = (64u16, 48u16);
+        let rgba: Vec<u8> = (0..usize::from(width) * usize::from(height))
+            .flat_map(|i| {
+                if i % 640 < 600 {
+                    [10, 20, 30, 0xFF]
+                } else {
+                    [i as u8, 7, 9, 0xFF]
+                }
+            })
+            .collect();
+
+        let encoded =
+            encode_cbg_v1_with_key(&rgba, width, height, PixelLayout::Bgr, 0xDEAD_BEEF).unwrap();
+        assert_eq!(encoded[0x24..0x28], 0xDEAD_BEEFu32.to_le_bytes());
         let (decoded, _, _) = decrypt_cbg(&encoded).unwrap();
         assert_eq!(decoded, rgba);
+
+        let residual = [0u8, 0, 0, 5, 0, 6, 0, 0, 0, 0, 7, 0, 0];
+        for min_run in ZERO_RUN_THRESHOLDS {
+            let packed = pack_zeros(&residual, min_run);
+            let mut unpacked = [0xAAu8; 13];
+            unpack_zeros(&packed, &mut unpacked);
+            assert_eq!(unpacked, residual, "min_run {min_run}");
+        }
+        assert!(pack_zeros(&residual, 2).len() < residual.len() + 1);
     }
 
     /// Test that variable-length encoding round-trips correctly.
@@ -0,0 +1,620 @@
+//! Pluggable entry codecs.
+//!
+//! A [`Codec`] recognizes one format by its leading bytes, decodes one layer
+//! of it and, where supported, encodes assets back into it. A
+//! [`CodecRegistry`] tries its codecs in priority order; wrappers such as BSE
+//! decode to [`Decoded::Unwrapped`] bytes, which go through detection again.
+//!
+//! The built-in registry ([`CodecRegistry::builtin`]) reproduces the classic
+//! BSE → DSC → CBG → BGI → bw-OGG chain. Downstream crates can add codecs for
+//! game-specific variants with [`CodecRegistry::register`]; a priority above
+//! [`priority::BSE`] runs before every built-in codec.
+
+use std::{
+    fmt,
+    sync::{Arc, LazyLock},
+};
+
+use crate::{
+    DecodedAsset, ImageFormat, SourceFormat, bgi,
+    bse::{self, BseVersion},
+    bytes_asset, cbg, decrypt, dsc,
+    error::{ArcError, ArcResult},
+    manifest::Layer,
+    ogg,
+    write::PixelLayout,
+};
+
+/// Maximum number of wrapper layers [`CodecRegistry::decode`] peels off.
+pub const MAX_LAYERS: usize = 8;
+
+/// Priorities of the built-in codecs; higher runs first.
+pub mod priority {
+    pub const BSE: i32 = 500;
+    pub const DSC: i32 = 400;
+    pub const CBG: i32 = 300;
+    pub const BGI: i32 = 200;
+    pub const BW_OGG: i32 = 100;
+}
+
+/// Result of decoding one layer with a [`Codec`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded {
+    /// Fully decoded content.
+    Asset(DecodedAsset),
+    /// A wrapper was removed; the inner bytes are detected again.
+    Unwrapped(Vec<u8>),
+}
+
+/// How [`Codec::encode`] stores an image. Each codec uses the fields that
+/// apply to its format; other assets ignore them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncodeParams {
+    /// Stored pixel layout; `None` picks the smallest lossless one.
+    pub layout: Option<PixelLayout>,
+    /// Store BGI pixels scrambled.
+    pub scramble: bool,
+    /// Key for the CBG V1 weight-table encryption; `None` derives one from
+    /// the image.
+    pub cbg_key: Option<u32>,
+    /// Write a lossy CBG V2 image with this quality instead of CBG V1.
+    pub cbg_quality: Option<u8>,
+}
+
+/// One entry format: detection, decoding and (optionally) encoding.
+pub trait Codec: Send + Sync {
+    /// Short identifier, e.g. `"cbg"`; used by [`CodecRegistry::get`].
+    fn name(&self) -> &'static str;
+
+    /// Whether `data` looks like this codec's format.
+    fn sniff(&self, data: &[u8]) -> bool;
+
+    /// Decode one layer of `data`, which passed [`Codec::sniff`].
+    fn decode(&self, data: &[u8]) -> ArcResult<Decoded>;
+
+    /// The manifest [`Layer`] describing how `data` was stored, given what
+    /// [`Codec::decode`] made of it. Defaults to [`Layer::Codec`] with this
+    /// codec's name.
+    fn layer(&self, data: &[u8], decoded: &Decoded) -> Layer {
+        let _ = (data, decoded);
+        Layer::Codec(self.name().to_owned())
+    }
+
+    /// Encode `asset` into this codec's format, storing images as `params`
+    /// asks.
+    ///
+    /// Wrapper codecs take [`DecodedAsset::Raw`] bytes. The default
+    /// implementation supports nothing.
+    fn encode(&self, asset: &DecodedAsset, params: EncodeParams) -> ArcResult<Vec<u8>> {
+        let _ = (asset, params);
+        Err(ArcError::EncodeUnsupported(self.name()))
+    }
+}
+
+/// Priority-ordered set of codecs.
+///
+/// Cloning is cheap; codecs are shared.
+#[derive(Clone, Default)]
+pub struct CodecRegistry {
+    /// Sorted by descending priority; equal priorities keep insertion order.
+    codecs: Vec<(i32, Arc<dyn Codec>)>,
+}
+
+static BUILTIN: LazyLock<CodecRegistry> = LazyLock::new(|| {
+    let mut registry = CodecRegistry::new();
+    registry.register(priority::BSE, BseCodec);
+    registry.register(priority::DSC, DscCodec);
+    registry.register(priority::CBG, CbgCodec);
+    registry.register(priority::BGI, BgiCodec);
+    registry.register(priority::BW_OGG, BwOggCodec);
+    registry
+});
+
+impl CodecRegistry {
+    /// An empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The built-in codecs, shared by [`crate::decode_bytes`] and packing.
+    #[must_use]
+    pub fn builtin() -> &'static Self {
+        &BUILTIN
+    }
+
+    /// Add a codec. It is tried before codecs with a lower priority and
+    /// after those with the same or a higher one.
+    pub fn register(&mut self, priority: i32, codec: impl Codec + 'static) {
+        let at = self.codecs.partition_point(|(p, _)| *p >= priority);
+        self.codecs.insert(at, (priority, Arc::new(codec)));
+    }
+
+    /// Builder form of [`CodecRegistry::register`].
+    #[must_use]
+    pub fn with(mut self, priority: i32, codec: impl Codec + 'static) -> Self {
+        self.register(priority, codec);
+        self
+    }
+
+    /// Codecs in the order they are tried.
+    pub fn codecs(&self) -> impl Iterator<Item = &dyn Codec> {
+        self.codecs.iter().map(|(_, c)| c.as_ref())
+    }
+
+    /// The highest-priority codec with the given name.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&dyn Codec> {
+        self.codecs().find(|c| c.name() == name)
+    }
+
+    /// The first codec whose [`Codec::sniff`] accepts `data`.
+    #[must_use]
+    pub fn detect(&self, data: &[u8]) -> Option<&dyn Codec> {
+        self.codecs().find(|c| c.sniff(data))
+    }
+
+    /// Decode `data` through every matching layer. Data no codec recognizes
+    /// is returned as a [`DecodedAsset::Script`] or [`DecodedAsset::Raw`].
+    ///
+    /// Fails with [`ArcError::TooManyLayers`] after [`MAX_LAYERS`] wrappers,
+    /// so self-nesting data cannot loop forever.
+    pub fn decode(&self, data: &[u8]) -> ArcResult<DecodedAsset> {
+        self.decode_layers(data).map(|(asset, _)| asset)
+    }
+
+    /// Like [`CodecRegistry::decode`], also returning the [`Layer`] of every
+    /// codec that was applied, outermost first.
+    pub fn decode_layers(&self, data: &[u8]) -> ArcResult<(DecodedAsset, Vec<Layer>)> {
+        let mut layers = Vec::new();
+        let Some(codec) = self.detect(data) else {
+            return Ok((bytes_asset(data.to_vec()), layers));
+        };
+        let mut decoded = codec.decode(data)?;
+        layers.push(codec.layer(data, &decoded));
+        for _ in 0..MAX_LAYERS {
+            match decoded {
+                Decoded::Asset(asset) => return Ok((asset, layers)),
+                Decoded::Unwrapped(inner) => match self.detect(&inner) {
+                    Some(codec) => {
+                        decoded = codec.decode(&inner)?;
+                        layers.push(codec.layer(&inner, &decoded));
+                    }
+                    None => return Ok((bytes_asset(inner), layers)),
+                },
+            }
+        }
+        match decoded {
+            Decoded::Asset(asset) => Ok((asset, layers)),
+            Decoded::Unwrapped(_) => Err(ArcError::TooManyLayers(MAX_LAYERS)),
+        }
+    }
+
+    /// Encode `asset` with the codec named `name`.
+    pub fn encode(
+        &self,
+        name: &str,
+        asset: &DecodedAsset,
+        params: EncodeParams,
+    ) -> ArcResult<Vec<u8>> {
+        self.get(name)
+            .ok_or_else(|| ArcError::UnknownCodec(name.to_owned()))?
+            .encode(asset, params)
+    }
+}
+
+impl fmt::Debug for CodecRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.codecs.iter().map(|(p, c)| (c.name(), p)))
+            .finish()
+    }
+}
+
+/// Layout of an image header's `bpp`; anything unusual is re-encoded as BGR.
+fn stored_layout(bpp: u16) -> PixelLayout {
+    PixelLayout::from_bpp(bpp).unwrap_or(PixelLayout::Bgr)
+}
+
+fn image((rgba, width, height): (Vec<u8>, u16, u16), source_format: SourceFormat) -> Decoded {
+    Decoded::Asset(DecodedAsset::Image {
+        rgba,
+        width,
+        height,
+        source_format,
+    })
+}
+
+/// BSE 1.0/1.1 header encryption. Encodes raw payloads and scripts as BSE 1.0.
+pub struct BseCodec;
+
+impl Codec for BseCodec {
+    fn name(&self) -> &'static str {
+        "bse"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        bse::is_bse(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+        Ok(Decoded::Unwrapped(bse::strip(data)?))
+    }
+
+    fn layer(&self, data: &[u8], _: &Decoded) -> Layer {
+        if u16::from_le_bytes([data[8], data[9]]) == BseVersion::V101.raw() {
+            Layer::Bse(BseVersion::V101)
+        } else {
+            Layer::Bse(BseVersion::V100)
+        }
+    }
+
+    fn encode(&self, asset: &DecodedAsset, _: EncodeParams) -> ArcResult<Vec<u8>> {
+        match asset {
+            DecodedAsset::Script(data) | DecodedAsset::Raw(data) => {
+                bse::encrypt_bse(data, BseVersion::V100, decrypt::content_key(data))
+            }
+            _ => Err(ArcError::EncodeUnsupported(self.name())),
+        }
+    }
+}
+
+/// DSC FORMAT 1.00 compression. Encodes images in the raw DSC image form and
+/// compresses scripts and raw data as-is.
+pub struct DscCodec;
+
+impl Codec for DscCodec {
+    fn name(&self) -> &'static str {
+        "dsc"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        dsc::is_dsc(data)
+    }
+
+    /// Raw DSC images are decoded directly; any other output (BGI or CBG
+    /// images, bw-wrapped audio, ...) goes back through detection.
+    fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+        let (mut decrypted, size) = dsc::decrypt_dsc(data)?;
+        if let Some(pixels) = dsc::decode_image(&decrypted, size) {
+            Ok(image(pixels, SourceFormat::Dsc))
+        } else {
+            decrypted.truncate(size as usize);
+            Ok(Decoded::Unwrapped(decrypted))
+        }
+    }
+
+    /// A raw image's depth comes from the header's decompressed size, which
+    /// covers the 16-byte image header and the pixels.
+    fn layer(&self, data: &[u8], decoded: &Decoded) -> Layer {
+        match decoded {
+            Decoded::Asset(DecodedAsset::Image { width, height, .. }) => {
+                let size = u32::from_le_bytes([data[20], data[21], data[22], data[23]]);
+                let bytes = size.saturating_sub(16) / (u32::from(*width) * u32::from(*height));
+                let bpp = u16::try_from(bytes * 8).unwrap_or_default();
+                Layer::Image(ImageFormat::Dsc, stored_layout(bpp))
+            }
+            _ => Layer::Dsc,
+        }
+    }
+
+    fn encode(&self, asset: &DecodedAsset, params: EncodeParams) -> ArcResult<Vec<u8>> {
+        match asset {
+            DecodedAsset::Image {
+                rgba,
+                width,
+                height,
+                ..
+            } => {
+                let layout = params.layout.unwrap_or_else(|| PixelLayout::detect(rgba));
+                dsc::encode_dsc(&dsc::raw_image(rgba, *width, *height, layout))
+            }
+            DecodedAsset::Script(data) | DecodedAsset::Raw(data) => dsc::encode_dsc(data),
+            DecodedAsset::Audio { .. } => Err(ArcError::EncodeUnsupported(self.name())),
+        }
+    }
+}
+
+/// `CompressedBG` V1/V2 images; encodes V1, or V2 given
+/// [`EncodeParams::cbg_quality`].
+pub struct CbgCodec;
+
+impl Codec for CbgCodec {
+    fn name(&self) -> &'static str {
+        "cbg"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        cbg::is_cbg(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+        Ok(image(cbg::decrypt_cbg(data)?, SourceFormat::Cbg))
+    }
+
+    fn layer(&self, data: &[u8], _: &Decoded) -> Layer {
+        let format = if u16::from_le_bytes([data[0x2E], data[0x2F]]) == 2 {
+            ImageFormat::CbgV2
+        } else {
+            ImageFormat::CbgV1
+        };
+        Layer::Image(
+            format,
+            stored_layout(u16::from_le_bytes([data[0x14], data[0x15]])),
+        )
+    }
+
+    fn encode(&self, asset: &DecodedAsset, params: EncodeParams) -> ArcResult<Vec<u8>> {
+        match asset {
+            DecodedAsset::Image {
+                rgba,
+                width,
+                height,
+                ..
+            } => {
+                let layout = params.layout.unwrap_or_else(|| PixelLayout::detect(rgba));
+                match (params.cbg_quality, params.cbg_key) {
+                    (Some(quality), _) => {
+                        cbg::encode_cbg_v2(rgba, *width, *height, layout, quality)
+                    }
+                    (None, Some(key)) => {
+                        cbg::encode_cbg_v1_with_key(rgba, *width, *height, layout, key)
+                    }
+                    (None, None) => cbg::encode_cbg_v1(rgba, *width, *height, layout),
+                }
+            }
+            _ => Err(ArcError::EncodeUnsupported(self.name())),
+        }
+    }
+}
+
+/// BGI uncompressed images.
+pub struct BgiCodec;
+
+impl Codec for BgiCodec {
+    fn name(&self) -> &'static str {
+        "bgi"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        bgi::is_bgi(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+        Ok(image(bgi::decrypt_bgi(data)?, SourceFormat::Bgi))
+    }
+
+    fn layer(&self, data: &[u8], _: &Decoded) -> Layer {
+        let format = if data[6] == 1 {
+            ImageFormat::BgiScrambled
+        } else {
+            ImageFormat::Bgi
+        };
+        Layer::Image(
+            format,
+            stored_layout(u16::from_le_bytes([data[4], data[5]])),
+        )
+    }
+
+    fn encode(&self, asset: &DecodedAsset, params: EncodeParams) -> ArcResult<Vec<u8>> {
+        match asset {
+            DecodedAsset::Image {
+                rgba,
+                width,
+                height,
+                ..
+            } => {
+                let layout = params.layout.unwrap_or_else(|| PixelLayout::detect(rgba));
+                Ok(if params.scramble {
+                    bgi::encode_bgi_scrambled(rgba, *width, *height, layout)
+                } else {
+                    bgi::encode_bgi(rgba, *width, *height, layout)
+                })
+            }
+            _ => Err(ArcError::EncodeUnsupported(self.name())),
+        }
+    }
+}
+
+/// OGG Vorbis audio behind the BGI `bw  ` header.
+pub struct BwOggCodec;
+
+impl Codec for BwOggCodec {
+    fn name(&self) -> &'static str {
+        "bw-ogg"
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        ogg::is_bgi_ogg(data)
+    }
+
+    fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+        let ogg = ogg::remove_header(data);
+        let header = data[..data.len() - ogg.len()].to_vec();
+        Ok(Decoded::Asset(DecodedAsset::Audio { ogg, header }))
+    }
+
+    fn layer(&self, _: &[u8], _: &Decoded) -> Layer {
+        Layer::Audio
+    }
+
+    /// Wraps the OGG stream in a fresh header; `header` is ignored.
+    fn encode(&self, asset: &DecodedAsset, _: EncodeParams) -> ArcResult<Vec<u8>> {
+        match asset {
+            DecodedAsset::Audio { ogg, .. } => Ok(ogg::add_header(ogg)),
+            _ => Err(ArcError::EncodeUnsupported(self.name())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Treats anything starting with `XOR!` as a one-byte XOR wrapper.
+    struct XorCodec;
+
+    impl Codec for XorCodec {
+        fn name(&self) -> &'static str {
+            "xor"
+        }
+
+        fn sniff(&self, data: &[u8]) -> bool {
+            data.starts_with(b"XOR!")
+        }
+
+        fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+            Ok(Decoded::Unwrapped(
+                data[4..].iter().map(|b| b ^ 0x5A).collect(),
+            ))
+        }
+
+        fn encode(&self, asset: &DecodedAsset, _: EncodeParams) -> ArcResult<Vec<u8>> {
+            match asset {
+                DecodedAsset::Raw(data) => Ok([
+                    b"XOR!".as_slice(),
+                    &data.iter().map(|b| b ^ 0x5A).collect::<Vec<_>>(),
+                ]
+                .concat()),
+                _ => Err(ArcError::EncodeUnsupported(self.name())),
+            }
+        }
+    }
+
+    /// Unwraps to itself forever.
+    struct LoopCodec;
+
+    impl Codec for LoopCodec {
+        fn name(&self) -> &'static str {
+            "loop"
+        }
+
+        fn sniff(&self, data: &[u8]) -> bool {
+            data.starts_with(b"LOOP")
+        }
+
+        fn decode(&self, data: &[u8]) -> ArcResult<Decoded> {
+            Ok(Decoded::Unwrapped(data.to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let registry = CodecRegistry::new().with(0, LoopCodec);
+        assert!(matches!(
+            registry.decode(b"LOOP"),
+            Err(ArcError::TooManyLayers(MAX_LAYERS))
+        ));
+    }
+
+    fn fixture_payload(arc: &'static [u8]) -> Vec<u8> {
+        crate::arc::Arc::from_bytes(arc)
+            .unwrap()
+            .get_file_data(0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dsc_wrapped_payloads() {
+        let registry = CodecRegistry::builtin();
+        let bgi = fixture_payload(include_bytes!("../test_assets/fixtures/arc_bgi.arc"));
+        let DecodedAsset::Image {
+            rgba,
+            width,
+            height,
+            ..
+        } = registry.decode(&bgi).unwrap()
+        else {
+            panic!("expected an image");
+        };
+
+        // A plain BGI image is also a raw DSC image and decodes the same.
+        match registry.decode(&dsc::encode_dsc(&bgi).unwrap()).unwrap() {
+            DecodedAsset::Image {
+                rgba: decoded,
+                source_format,
+                ..
+            } => {
+                assert_eq!(decoded, rgba);
+                assert_eq!(source_format, SourceFormat::Dsc);
+            }
+            other => panic!("expected an image, got {other:?}"),
+        }
+
+        // Anything else comes out of DSC and goes through detection again.
+        let payloads = [
+            bgi::encode_bgi_scrambled(&rgba, width, height, PixelLayout::Bgr),
+            fixture_payload(include_bytes!("../test_assets/fixtures/arc_cbg.arc")),
+            fixture_payload(include_bytes!("../test_assets/fixtures/arc_audio.arc")),
+        ];
+        for payload in payloads {
+            let (expected, inner) = registry.decode_layers(&payload).unwrap();
+            assert!(!matches!(expected, DecodedAsset::Raw(_)));
+            let wrapped = dsc::encode_dsc(&payload).unwrap();
+            let (decoded, layers) = registry.decode_layers(&wrapped).unwrap();
+            assert_eq!(decoded, expected);
+            assert_eq!(layers[0], Layer::Dsc);
+            assert_eq!(layers[1..], inner);
+        }
+    }
+
+    #[test]
+    fn test_dsc_image_layout() {
+        let registry = CodecRegistry::builtin();
+        let gray: Vec<u8> = (0..64u8).flat_map(|v| [v, v, v, 0xFF]).collect();
+        // Opaque and grey pixels stored at every depth keep that depth.
+        for layout in [PixelLayout::Gray, PixelLayout::Bgr, PixelLayout::Bgra] {
+            let stored = dsc::encode_dsc(&dsc::raw_image(&gray, 8, 8, layout)).unwrap();
+            let (asset, layers) = registry.decode_layers(&stored).unwrap();
+            assert_eq!(layers, [Layer::Image(ImageFormat::Dsc, layout)]);
+            let params = EncodeParams {
+                layout: Some(layout),
+                ..EncodeParams::default()
+            };
+            assert_eq!(registry.encode("dsc", &asset, params).unwrap(), stored);
+        }
+    }
+
+    #[test]
+    fn test_builtin_order() {
+        let names: Vec<_> = CodecRegistry::builtin().codecs().map(Codec::name).collect();
+        assert_eq!(names, ["bse", "dsc", "cbg", "bgi", "bw-ogg"]);
+    }
+
+    #[test]
+    fn test_register_custom_codec() {
+        let registry = CodecRegistry::builtin()
+            .clone()
+            .with(priority::BSE + 1, XorCodec);
+        assert_eq!(registry.codecs().next().unwrap().name(), "xor");
+
+        // A wrapped BGI-OGG is unwrapped by the custom codec, then decoded by
+        // the built-in one.
+        let ogg = include_bytes!("../test_assets/test.ogg");
+        let wrapped = ogg::add_header(ogg);
+        let xored = registry
+            .encode("xor", &DecodedAsset::Raw(wrapped), EncodeParams::default())
+            .unwrap();
+        assert!(CodecRegistry::builtin().get("xor").is_none());
+        match registry.decode(&xored).unwrap() {
+            DecodedAsset::Audio { ogg: decoded, .. } => assert_eq!(decoded, ogg),
+            other => panic!("expected audio, got {other:?}"),
+        }
+
+        assert!(matches!(
+            registry.encode(
+                "dsc",
+                &DecodedAsset::Audio {
+                    ogg: vec![],
+                    header: vec![]
+                },
+                EncodeParams::default()
+            ),
+            Err(ArcError::EncodeUnsupported("dsc"))
+        ));
+        assert!(matches!(
+            registry.encode("nope", &DecodedAsset::Raw(vec![]), EncodeParams::default()),
+            Err(ArcError::UnknownCodec(_))
+        ));
+    }
+}
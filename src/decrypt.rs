@@ -1,4 +1,5 @@
-//! BGI PRNG helpers ported from the original engine.
+//! BGI PRNG helpers ported from the original engine, plus the key derivation
+//! used by the encoders.
 
 // Integer casts mirror the C implementation.
 #![allow(clippy::cast_possible_truncation)]
@@ -25,3 +26,12 @@ pub(crate) fn hash_update(hash_val: &mut u32) -> u32 {
         .wrapping_add(1);
     eax & 0x7FFF
 }
+
+/// Deterministic per-payload key (FNV-1a of the payload) for the encoders,
+/// so that packing the same input twice produces the same archive while
+/// different assets still get different keys.
+pub(crate) fn content_key(data: &[u8]) -> u32 {
+    data.iter().fold(0x811C_9DC5, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
@@ -7,11 +7,16 @@
     clippy::too_many_arguments
 )]
 
-use std::{fs::File, io::Write, path::Path};
+use std::path::Path;
 
 use bytes::Buf;
 
-use crate::{decrypt::hash_update, error::ArcResult, write::write_rgba_to_png};
+use crate::{
+    bits::MsbBitWriter,
+    decrypt::hash_update,
+    error::{ArcError, ArcResult},
+    write::{PixelLayout, convert_bgr_to_rgba, write_rgba_to_png},
+};
 
 /// DSC Huffman tree node.
 #[derive(Debug, Clone)]
@@ -40,6 +45,10 @@ pub fn is_dsc(data: &[u8]) -> bool {
 /// Decrypt a DSC buffer, returning the decoded data and its size.
 #[allow(clippy::too_many_lines)]
 pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
+    // Header (32 bytes) + encrypted weight table (512 bytes)
+    if crypted.len() < 32 + 512 {
+        return Err(ArcError::DscDecryptError);
+    }
     let mut data_ptr = &crypted[16..];
 
     let mut hash = data_ptr.get_u32_le();
@@ -65,7 +74,7 @@ pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
     let mut vector0 = vec![0u32; 1024];
     let mut nn = 0;
     let mut toggle = 0x200;
-    let mut dec0 = 1;
+    let mut dec0: usize = 1;
     let mut value_set = 1;
     let mut v13_idx = 0;
 
@@ -76,38 +85,53 @@ pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
         let mut group_count = 0;
 
         while buffer_cur < buffer.len() && nn == ((buffer[buffer_cur] >> 16) & 0xFFFF) {
-            nodes[vector0[v13_idx] as usize].has_childs = 0;
-            nodes[vector0[v13_idx] as usize].leaf_value = buffer[buffer_cur] & 0x1FF;
+            let node = slot_node(&mut nodes, &vector0, v13_idx)?;
+            node.has_childs = 0;
+            node.leaf_value = buffer[buffer_cur] & 0x1FF;
             buffer_cur += 1;
             v13_idx += 1;
             group_count += 1;
         }
 
-        let v18 = 2 * (dec0 - group_count);
-        if group_count < dec0 {
-            dec0 -= group_count;
-            for _ in 0..dec0 {
-                nodes[vector0[v13_idx] as usize].has_childs = 1;
-                for m in 0..2 {
-                    vector0[vector0_ptr_idx] = value_set;
-                    nodes[vector0[v13_idx] as usize].childs[m] = value_set;
-                    value_set += 1;
-                    vector0_ptr_idx += 1;
+        // More codes of this length than the tree has free slots for.
+        let free = dec0
+            .checked_sub(group_count)
+            .ok_or(ArcError::DscDecryptError)?;
+        for _ in 0..free {
+            slot_node(&mut nodes, &vector0, v13_idx)?.has_childs = 1;
+            for m in 0..2 {
+                if value_set as usize >= nodes.len() || vector0_ptr_idx >= vector0.len() {
+                    return Err(ArcError::DscDecryptError);
                 }
-                v13_idx += 1;
+                vector0[vector0_ptr_idx] = value_set;
+                slot_node(&mut nodes, &vector0, v13_idx)?.childs[m] = value_set;
+                value_set += 1;
+                vector0_ptr_idx += 1;
             }
+            v13_idx += 1;
         }
-        dec0 = v18;
+        dec0 = 2 * free;
         v13_idx = vector0_ptr_init_idx;
         toggle ^= 0x200;
         nn += 1;
     }
 
+    let src = &crypted[32 + 512..];
+    // An empty tree decodes nothing; otherwise each code takes at least one
+    // bit and expands to at most 257 bytes.
+    if size > 0 && (buffer.is_empty() || u64::from(size) > src.len() as u64 * 8 * 257) {
+        return Err(ArcError::DscDecryptError);
+    }
+
     // Decompress the payload
     let mut data = vec![0u8; size as usize];
-    let src_ptr_start = 32 + 512;
+    let next_byte = |src_ptr: &mut usize| {
+        let byte = src.get(*src_ptr).ok_or(ArcError::DscDecryptError)?;
+        *src_ptr += 1;
+        Ok::<_, ArcError>(u32::from(*byte))
+    };
 
-    let src_end = crypted.len() - src_ptr_start;
+    let src_end = src.len();
     let dst_end = size;
 
     let mut src_ptr = 0;
@@ -123,8 +147,7 @@ pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
         while nodes[nentry as usize].has_childs != 0 {
             if nbits == 0 {
                 nbits = 8;
-                bits = u32::from(crypted[src_ptr_start + src_ptr]);
-                src_ptr += 1;
+                bits = next_byte(&mut src_ptr)?;
             }
 
             let bit = (bits >> 7) & 1;
@@ -145,9 +168,7 @@ pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
                 let bytes = ((11 - nbits) >> 3) + 1;
                 let mut bytes_left = bytes;
                 while bytes_left > 0 {
-                    let next_byte = u32::from(crypted[src_ptr_start + src_ptr]);
-                    cvalue = next_byte + (cvalue << 8);
-                    src_ptr += 1;
+                    cvalue = next_byte(&mut src_ptr)? + (cvalue << 8);
                     nbits2 += 8;
                     bytes_left -= 1;
                 }
@@ -157,8 +178,10 @@ pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
             bits = (cvalue << (8 - (nbits2 - 12))) & 0xFF;
 
             let offset = (cvalue >> (nbits2 - 12)) + 2;
-            let mut ring_ptr = dst_ptr - offset;
-            let mut count = u32::from(info & 0xFF) + 2;
+            let mut ring_ptr = dst_ptr
+                .checked_sub(offset)
+                .ok_or(ArcError::DscDecryptError)?;
+            let mut count = (u32::from(info & 0xFF) + 2).min(dst_end - dst_ptr);
 
             while count > 0 {
                 let tmp = data[ring_ptr as usize];
@@ -177,6 +200,19 @@ pub fn decrypt_dsc(crypted: &[u8]) -> ArcResult<(Vec<u8>, u32)> {
     Ok((data, size))
 }
 
+/// The tree node held by decode slot `idx`, failing on a malformed tree that
+/// runs past the slot or node tables.
+fn slot_node<'a>(
+    nodes: &'a mut [NodeDSC],
+    slots: &[u32],
+    idx: usize,
+) -> ArcResult<&'a mut NodeDSC> {
+    let slot = *slots.get(idx).ok_or(ArcError::DscDecryptError)?;
+    nodes
+        .get_mut(slot as usize)
+        .ok_or(ArcError::DscDecryptError)
+}
+
 /// Check whether the decoded data looks like a BGI image header.
 #[must_use]
 pub fn is_image(data: &[u8]) -> bool {
@@ -210,35 +246,34 @@ pub fn is_image(data: &[u8]) -> bool {
     true
 }
 
-/// Save DSC data, save as PNG if it's an image, otherwise save as raw file
-pub fn save(data: &[u8], size: u32, savepath: impl AsRef<Path>) -> ArcResult<()> {
-    if size > 15 && is_image(data) {
-        let mut data_ptr = data;
-        let width = data_ptr.get_u16_le();
-        let height = data_ptr.get_u16_le();
-        let bpp = data_ptr.get_u8();
-        data_ptr = &data_ptr[11..]; // Skip 11 zero bytes
-
-        let total = height as usize * width as usize;
-        let pixels: Vec<u8> = (0..total)
-            .flat_map(|_| {
-                let (r, g, b, a) = match bpp {
-                    8 => {
-                        let v = data_ptr.get_u8();
-                        (v, v, v, 255)
-                    }
-                    32 => (
-                        data_ptr.get_u8(),
-                        data_ptr.get_u8(),
-                        data_ptr.get_u8(),
-                        data_ptr.get_u8(),
-                    ),
-                    _ => (data_ptr.get_u8(), data_ptr.get_u8(), data_ptr.get_u8(), 255),
-                };
-                [r, g, b, a]
-            })
-            .collect();
+/// Convert decrypted DSC data to RGBA pixels if it is a raw image, returning
+/// (RGBA pixels, width, height).
+///
+/// Raw images share the plain BGI layout: pixels are stored as grey, BGR or
+/// BGRA.
+#[must_use]
+pub fn decode_image(data: &[u8], size: u32) -> Option<(Vec<u8>, u16, u16)> {
+    if size <= 15 || !is_image(data) {
+        return None;
+    }
+    let mut data_ptr = data;
+    let width = data_ptr.get_u16_le();
+    let height = data_ptr.get_u16_le();
+    let bpp = data_ptr.get_u8();
+    data_ptr = &data_ptr[11..]; // Skip 11 zero bytes
+
+    let total = height as usize * width as usize;
+    if data_ptr.len() < total * usize::from(bpp / 8) {
+        return None;
+    }
+    let pixels = convert_bgr_to_rgba(data_ptr, width as usize, height as usize, u32::from(bpp));
+    Some((pixels, width, height))
+}
 
+/// Save DSC data, save as PNG if it's a raw image, otherwise decode it again
+/// with [`crate::decode_file`] (nested images, audio, or a raw file)
+pub fn save(data: &[u8], size: u32, savepath: impl AsRef<Path>) -> ArcResult<()> {
+    if let Some((pixels, width, height)) = decode_image(data, size) {
         write_rgba_to_png(
             width,
             height,
@@ -246,7 +281,317 @@ pub fn save(data: &[u8], size: u32, savepath: impl AsRef<Path>) -> ArcResult<()>
             savepath.as_ref().with_extension("png"),
         )?;
     } else {
-        File::create(savepath)?.write_all(&data[..size as usize])?;
+        crate::decode_file(&data[..size as usize], savepath)?;
     }
     Ok(())
 }
+
+// ---------------------------------------------------------------------------
+// Encoding (raw data → DSC FORMAT 1.00)
+// ---------------------------------------------------------------------------
+
+/// Key seeding the weight-table encryption. Any value works; a fixed one
+/// keeps output reproducible.
+const ENCODE_KEY: u32 = 0x1B5E_0D3A;
+
+/// Shortest back-reference worth emitting (symbol + 12 bits ≥ 2 literals).
+const MIN_MATCH: usize = 3;
+/// Longest back-reference: symbol `0x1FF` copies 0xFF + 2 bytes.
+const MAX_MATCH: usize = 0x101;
+/// Back-reference distances are stored in 12 bits, biased by 2.
+const MIN_DISTANCE: usize = 2;
+const MAX_DISTANCE: usize = 0xFFF + 2;
+/// Candidates examined per position before settling for the best so far.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: u32 = 15;
+
+/// One decoded unit: a literal byte or a copy from earlier output.
+enum Token {
+    Literal(u8),
+    Copy { len: usize, distance: usize },
+}
+
+/// Compress `data` into a `DSC FORMAT 1.00` stream; the inverse of
+/// [`decrypt_dsc`].
+///
+/// Layout: 16-byte magic, key, decompressed size, token count, padding, then
+/// 512 code lengths (one per symbol, encrypted with the key stream) and the
+/// MSB-first Huffman bitstream. Symbols `0..=0xFF` are literals; `0x100 + n`
+/// copies `n + 2` bytes from a 12-bit distance that follows the code.
+pub fn encode_dsc(data: &[u8]) -> ArcResult<Vec<u8>> {
+    let size = u32::try_from(data.len()).map_err(|_| ArcError::ArchiveTooLarge)?;
+    let tokens = tokenize(data);
+
+    let mut freq = [0u32; 512];
+    for token in &tokens {
+        freq[symbol(token)] += 1;
+    }
+    let lengths = code_lengths(&freq);
+    let codes = canonical_codes(&lengths);
+
+    let mut writer = MsbBitWriter::new();
+    for token in &tokens {
+        let (code, len) = codes[symbol(token)];
+        writer.write_bits(code, len);
+        if let Token::Copy { distance, .. } = token {
+            writer.write_bits((distance - MIN_DISTANCE) as u32, 12);
+        }
+    }
+    let bitstream = writer.finish();
+
+    let mut output = Vec::with_capacity(32 + 512 + bitstream.len());
+    output.extend_from_slice(b"DSC FORMAT 1.00\0");
+    output.extend_from_slice(&ENCODE_KEY.to_le_bytes());
+    output.extend_from_slice(&size.to_le_bytes());
+    output.extend_from_slice(&(tokens.len() as u32).to_le_bytes());
+    output.extend_from_slice(&0u32.to_le_bytes()); // padding
+
+    let mut hash = ENCODE_KEY;
+    for &len in &lengths {
+        output.push(len.wrapping_add((hash_update(&mut hash) & 0xFF) as u8));
+    }
+    output.extend_from_slice(&bitstream);
+    // The decoder stops once it has consumed the last input byte, even with
+    // bits still buffered; a trailing pad byte lets it finish the last token.
+    output.push(0);
+    Ok(output)
+}
+
+fn symbol(token: &Token) -> usize {
+    match *token {
+        Token::Literal(b) => usize::from(b),
+        Token::Copy { len, .. } => 0x100 + len - 2,
+    }
+}
+
+/// Hash chains over 3-byte prefixes of the input.
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    /// Most recent position per hash bucket.
+    head: Vec<u32>,
+    /// Previous position with the same hash, per position.
+    prev: Vec<u32>,
+}
+
+impl<'a> MatchFinder<'a> {
+    const NONE: u32 = u32::MAX;
+
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![Self::NONE; 1 << HASH_BITS],
+            prev: vec![Self::NONE; data.len()],
+        }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let d = self.data;
+        let v = u32::from(d[i]) | u32::from(d[i + 1]) << 8 | u32::from(d[i + 2]) << 16;
+        (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.data.len() {
+            let h = self.hash(i);
+            self.prev[i] = self.head[h];
+            self.head[h] = i as u32;
+        }
+    }
+
+    /// Longest `(len, distance)` match for position `i` among earlier
+    /// positions within reach.
+    fn longest(&self, i: usize) -> (usize, usize) {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if i + MIN_MATCH > self.data.len() {
+            return (best_len, best_distance);
+        }
+        let max_len = MAX_MATCH.min(self.data.len() - i);
+        let mut candidate = self.head[self.hash(i)];
+        let mut chain = 0;
+        while candidate != Self::NONE && chain < MAX_CHAIN {
+            let j = candidate as usize;
+            let distance = i - j;
+            if distance > MAX_DISTANCE {
+                break;
+            }
+            if distance >= MIN_DISTANCE {
+                let len = self.data[j..]
+                    .iter()
+                    .zip(&self.data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_distance) = (len, distance);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                chain += 1;
+            }
+            candidate = self.prev[j];
+        }
+        (best_len, best_distance)
+    }
+}
+
+/// Greedy LZ77 parse of `data`.
+fn tokenize(data: &[u8]) -> Vec<Token> {
+    let mut finder = MatchFinder::new(data);
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let (len, distance) = finder.longest(i);
+        if len >= MIN_MATCH {
+            tokens.push(Token::Copy { len, distance });
+            for k in i..i + len {
+                finder.insert(k);
+            }
+            i += len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            finder.insert(i);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Huffman code length per symbol (0 = unused). A lone symbol gets length 1,
+/// since the decoder's tree always has at least two branches.
+fn code_lengths(freq: &[u32; 512]) -> [u8; 512] {
+    use std::{cmp::Reverse, collections::BinaryHeap};
+
+    let mut lengths = [0u8; 512];
+    // Nodes 0..512 are leaves; merged nodes are appended after them.
+    let mut parent: Vec<usize> = vec![usize::MAX; 512];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = freq
+        .iter()
+        .enumerate()
+        .filter(|&(_, &f)| f > 0)
+        .map(|(i, &f)| Reverse((u64::from(f), i)))
+        .collect();
+
+    if heap.len() == 1 {
+        let Reverse((_, only)) = heap.pop().unwrap();
+        lengths[only] = 1;
+        return lengths;
+    }
+
+    while heap.len() > 1 {
+        let Reverse((w1, a)) = heap.pop().unwrap();
+        let Reverse((w2, b)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[a] = node;
+        parent[b] = node;
+        heap.push(Reverse((w1 + w2, node)));
+    }
+
+    for (sym, len) in lengths.iter_mut().enumerate() {
+        if freq[sym] == 0 {
+            continue;
+        }
+        let mut depth = 0u8;
+        let mut node = sym;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            depth += 1;
+        }
+        *len = depth;
+    }
+    lengths
+}
+
+/// Canonical `(code, length)` per symbol: shorter codes first, ties broken by
+/// symbol value, matching the level-by-level tree [`decrypt_dsc`] builds.
+fn canonical_codes(lengths: &[u8; 512]) -> Vec<(u32, u32)> {
+    let mut order: Vec<usize> = (0..512).filter(|&s| lengths[s] > 0).collect();
+    order.sort_by_key(|&s| (lengths[s], s));
+
+    let mut codes = vec![(0u32, 0u32); 512];
+    let mut code = 0u32;
+    let mut prev_len = order.first().map_or(0, |&s| u32::from(lengths[s]));
+    for s in order {
+        let len = u32::from(lengths[s]);
+        code <<= len - prev_len;
+        codes[s] = (code, len);
+        code += 1;
+        prev_len = len;
+    }
+    codes
+}
+
+/// Build the raw image form recognized by [`is_image`] and decoded by
+/// [`decode_image`]: width, height, bpp (one byte), 11 zero bytes, then
+/// pixels in `layout` (grey, BGR or BGRA, as the engine reads them).
+#[must_use]
+pub fn raw_image(rgba: &[u8], width: u16, height: u16, layout: PixelLayout) -> Vec<u8> {
+    let pixels = layout.pack(rgba);
+    let mut output = Vec::with_capacity(16 + pixels.len());
+    output.extend_from_slice(&width.to_le_bytes());
+    output.extend_from_slice(&height.to_le_bytes());
+    output.push(layout.bpp() as u8);
+    output.extend_from_slice(&[0; 11]);
+    output.extend_from_slice(&pixels);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) {
+        let encoded = encode_dsc(data).unwrap();
+        assert!(is_dsc(&encoded));
+        let (decoded, size) = decrypt_dsc(&encoded).unwrap();
+        assert_eq!(size as usize, data.len());
+        assert_eq!(&decoded[..size as usize], data);
+    }
+
+    #[test]
+    fn test_encode_dsc_round_trip() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(&[7u8; 5000]);
+        round_trip(b"abababababab the quick brown fox, the quick brown fox");
+
+        // Pseudo-random bytes with long repeats, some beyond the window.
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..6000)
+            .map(|_| (hash_update(&mut seed) >> 3) as u8)
+            .collect();
+        let data = [&noise[..], &noise[..3000], &noise[100..200], &noise[..]].concat();
+        round_trip(&data);
+        assert!(encode_dsc(&data).unwrap().len() < data.len());
+    }
+
+    #[test]
+    fn test_raw_image_round_trip() {
+        let rgba: Vec<u8> = (0..4 * 3 * 4).map(|i| i as u8).collect();
+        let raw = raw_image(&rgba, 4, 3, PixelLayout::Bgra);
+        assert!(is_image(&raw));
+        assert_eq!(raw[16..20], [2, 1, 0, 3]);
+        let (pixels, w, h) = decode_image(&raw, raw.len() as u32).unwrap();
+        assert_eq!((w, h), (4, 3));
+        assert_eq!(pixels, rgba);
+
+        let gray: Vec<u8> = (0..4 * 3).flat_map(|i| [i, i, i, 0xFF]).collect();
+        let raw = raw_image(&gray, 4, 3, PixelLayout::Gray);
+        assert_eq!(raw.len(), 16 + 4 * 3);
+        assert_eq!(decode_image(&raw, raw.len() as u32).unwrap().0, gray);
+    }
+
+    #[test]
+    fn test_raw_image_matches_plain_bgi() {
+        // A raw DSC image is a plain BGI image; both decode to the same pixels.
+        let arc = include_bytes!("../test_assets/fixtures/arc_bgi.arc");
+        let bgi = crate::arc::Arc::from_bytes(&arc[..])
+            .unwrap()
+            .get_file_data(0)
+            .unwrap();
+        assert!(is_image(&bgi));
+        let (pixels, w, h) = decode_image(&bgi, bgi.len() as u32).unwrap();
+        assert_eq!((pixels, w, h), crate::bgi::decrypt_bgi(&bgi).unwrap());
+    }
+}
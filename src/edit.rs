@@ -0,0 +1,513 @@
+//! In-place editing of existing ARC archives.
+//!
+//! Entry offsets are relative to the data region, which starts right after
+//! the index. [`ArcEditor`] keeps every untouched entry at its absolute file
+//! position and only rewrites the index, so a patch touching a few entries of
+//! a multi-gigabyte archive writes a few entries' worth of data:
+//!
+//! - **replace**: written over the old payload if it fits and no other entry
+//!   shares it, else appended
+//! - **add**: appended at the end of the file
+//! - **remove** / **rename**: index-only changes
+//!
+//! Nothing is written until [`ArcEditor::commit`], which also lays out every
+//! payload and index offset before its first write, so a failed edit or an
+//! archive that would outgrow the format leaves the file untouched. The
+//! commit itself is not atomic: a crash or I/O error after payloads were
+//! moved or overwritten in place, but before the new index is written, leaves
+//! the old index pointing at changed data. When the index grows, entries
+//! whose data overlaps the larger index are moved to the end of the file.
+//! Space freed by removed or shrunk entries is left in place as unused gaps.
+
+// ARC format uses u32 for offsets/sizes; intentional truncation in format code.
+#![allow(clippy::cast_possible_truncation)]
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use log::debug;
+
+use crate::{
+    EncodeOptions, ImageFormat,
+    arc::{self, Arc, ArcFile, ArcVersion, NameEncoding},
+    codec::CodecRegistry,
+    encode_for_pack,
+    error::{ArcError, ArcResult},
+};
+
+/// An index entry whose data position is tracked as an absolute file offset.
+struct Entry {
+    name: Vec<u8>,
+    /// Current payload in the file; an added entry has none (`size` 0).
+    start: u64,
+    size: u32,
+    /// Index bytes after the offset and size, written back unchanged.
+    padding: Vec<u8>,
+    /// New payload, written by [`ArcEditor::commit`].
+    pending: Option<Vec<u8>>,
+}
+
+/// Editor for an existing ARC file.
+///
+/// All changes, including new payloads, are kept in memory until
+/// [`ArcEditor::commit`]; dropping the editor discards them.
+pub struct ArcEditor {
+    file: File,
+    version: ArcVersion,
+    name_encoding: NameEncoding,
+    encode: EncodeOptions,
+    codecs: CodecRegistry,
+    entries: Vec<Entry>,
+    /// Data region start of the archive as opened.
+    data_start: u64,
+    /// Current end of file, where appended payloads go.
+    end: u64,
+}
+
+impl ArcEditor {
+    /// Open an ARC file for editing.
+    pub fn open<P: AsRef<Path>>(filename: P) -> ArcResult<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(filename)?;
+        let arc = Arc::from_reader(file)?;
+        let version = arc.version();
+        let (mut file, data_offset, files) = arc.into_parts();
+        let end = file.seek(SeekFrom::End(0))?;
+
+        let entries = files
+            .into_iter()
+            .map(|f| Entry {
+                name: f.name,
+                start: data_offset + u64::from(f.offset),
+                size: f.size,
+                padding: f.padding,
+                pending: None,
+            })
+            .collect();
+
+        Ok(Self {
+            file,
+            version,
+            name_encoding: NameEncoding::default(),
+            encode: EncodeOptions::default(),
+            codecs: CodecRegistry::builtin().clone(),
+            entries,
+            data_start: data_offset,
+            end,
+        })
+    }
+
+    /// Charset used to match and encode entry names (CP932 by default).
+    #[must_use]
+    pub fn with_name_encoding(mut self, encoding: NameEncoding) -> Self {
+        self.name_encoding = encoding;
+        self
+    }
+
+    /// Encoding applied to PNG images by [`ArcEditor::replace`] and
+    /// [`ArcEditor::add`].
+    #[must_use]
+    pub fn with_image_format(mut self, format: ImageFormat) -> Self {
+        self.encode.image_format = Some(format);
+        self
+    }
+
+    /// Conversion applied to payloads by [`ArcEditor::replace`] and
+    /// [`ArcEditor::add`]. Replaces any format set with
+    /// [`ArcEditor::with_image_format`].
+    #[must_use]
+    pub fn with_encode_options(mut self, options: EncodeOptions) -> Self {
+        self.encode = options;
+        self
+    }
+
+    /// Codecs used by [`ArcEditor::replace`] and [`ArcEditor::add`] (the
+    /// built-in ones by default).
+    #[must_use]
+    pub fn with_codecs(mut self, codecs: CodecRegistry) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Replace the payload of entry `name` with a source file (PNG, OGG,
+    /// anything else as-is), converted the same way as [`crate::pack_arc`].
+    pub fn replace(&mut self, name: &str, data: &[u8]) -> ArcResult<()> {
+        let (encoded, _) = encode_for_pack(name, data, self.encode, &self.codecs)?;
+        self.replace_encoded(name, &encoded)
+    }
+
+    /// Replace the payload of entry `name` with already-encoded data.
+    pub fn replace_encoded(&mut self, name: &str, payload: &[u8]) -> ArcResult<()> {
+        let idx = self.position(name)?;
+        payload_size(payload)?;
+        self.entries[idx].pending = Some(payload.to_vec());
+        Ok(())
+    }
+
+    /// Append a new entry from a source file, converted the same way as
+    /// [`crate::pack_arc`].
+    pub fn add(&mut self, name: &str, data: &[u8]) -> ArcResult<()> {
+        let (encoded, _) = encode_for_pack(name, data, self.encode, &self.codecs)?;
+        self.add_encoded(name, &encoded)
+    }
+
+    /// Append a new entry with already-encoded data.
+    pub fn add_encoded(&mut self, name: &str, payload: &[u8]) -> ArcResult<()> {
+        let field = self.name_field(name)?;
+        if self.find(name)?.is_some() {
+            return Err(ArcError::DuplicateEntry(name.to_owned()));
+        }
+        payload_size(payload)?;
+        self.entries.push(Entry {
+            name: field,
+            start: 0,
+            size: 0,
+            padding: Vec::new(),
+            pending: Some(payload.to_vec()),
+        });
+        Ok(())
+    }
+
+    /// Remove entry `name` from the index. Its data is left as an unused gap.
+    pub fn remove(&mut self, name: &str) -> ArcResult<()> {
+        let idx = self.position(name)?;
+        self.entries.remove(idx);
+        Ok(())
+    }
+
+    /// Rename entry `old` to `new`.
+    pub fn rename(&mut self, old: &str, new: &str) -> ArcResult<()> {
+        let field = self.name_field(new)?;
+        if self.find(new)?.is_some() {
+            return Err(ArcError::DuplicateEntry(new.to_owned()));
+        }
+        let idx = self.position(old)?;
+        self.entries[idx].name = field;
+        Ok(())
+    }
+
+    /// Write the new payloads and the updated index, first moving any entry
+    /// data that the new index would overlap to the end of the file.
+    ///
+    /// The whole layout, including every index offset, is worked out before
+    /// anything is written.
+    pub fn commit(mut self) -> ArcResult<()> {
+        let data_start = arc::index_len(self.version, self.entries.len() as u64);
+        self.end = self.end.max(data_start);
+
+        let mut moves = Vec::new();
+        for i in 0..self.entries.len() {
+            let Entry { start, size, .. } = self.entries[i];
+            if self.entries[i].pending.is_none() && start < data_start {
+                debug!("moving entry {i} out of the index region");
+                let to = self.end;
+                moves.push((start, to, u64::from(size)));
+                self.entries[i].start = to;
+                self.end = to + u64::from(size);
+            }
+        }
+
+        let mut writes = Vec::new();
+        for i in 0..self.entries.len() {
+            let Some(payload) = self.entries[i].pending.take() else {
+                continue;
+            };
+            let size = payload_size(&payload)?;
+            let start = if self.reusable(i, data_start, size) {
+                debug!("writing entry {i} in place");
+                self.entries[i].start
+            } else {
+                debug!("writing entry {i} at end of file");
+                self.end
+            };
+            self.end = self.end.max(start + u64::from(size));
+            self.entries[i].start = start;
+            self.entries[i].size = size;
+            writes.push((start, payload));
+        }
+
+        let files = self
+            .entries
+            .iter()
+            .map(|e| {
+                let offset =
+                    u32::try_from(e.start - data_start).map_err(|_| ArcError::ArchiveTooLarge)?;
+                Ok(ArcFile {
+                    name: e.name.clone(),
+                    offset,
+                    size: e.size,
+                    padding: e.padding.clone(),
+                })
+            })
+            .collect::<ArcResult<Vec<_>>>()?;
+
+        for (from, to, len) in moves {
+            arc::move_region(&mut self.file, from, to, len)?;
+        }
+        for (start, payload) in writes {
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.write_all(&payload)?;
+        }
+
+        self.file.seek(SeekFrom::Start(0))?;
+        arc::write_index(&mut self.file, self.version, &files)?;
+
+        // A shrinking index leaves old index bytes behind; zero them so the
+        // gap does not look like stale entries.
+        if data_start < self.data_start {
+            self.file
+                .write_all(&vec![0u8; (self.data_start - data_start) as usize])?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Whether a new payload of `size` bytes for entry `i` can overwrite its
+    /// old one: it fits, lies past the index and no other entry still uses
+    /// any of those bytes.
+    fn reusable(&self, i: usize, data_start: u64, size: u32) -> bool {
+        let Entry {
+            start, size: old, ..
+        } = self.entries[i];
+        let end = start + u64::from(old);
+        size <= old
+            && start >= data_start
+            && self.entries.iter().enumerate().all(|(j, other)| {
+                j == i
+                    || other.pending.is_some()
+                    || other.size == 0
+                    || other.start >= end
+                    || other.start + u64::from(other.size) <= start
+            })
+    }
+
+    /// Index of the entry named `name`, if any. A name too long for the
+    /// index matches nothing.
+    fn find(&self, name: &str) -> ArcResult<Option<usize>> {
+        let raw = self.name_encoding.encode(name, usize::MAX)?;
+        Ok(self
+            .entries
+            .iter()
+            .position(|e| arc::trim_name(&e.name) == raw))
+    }
+
+    fn position(&self, name: &str) -> ArcResult<usize> {
+        self.find(name)?
+            .ok_or_else(|| ArcError::EntryNotFound(name.to_owned()))
+    }
+
+    /// Encode `name` into a full, null-padded index name field, rejecting
+    /// names that do not fit instead of truncating them.
+    fn name_field(&self, name: &str) -> ArcResult<Vec<u8>> {
+        let limit = self.version.name_len();
+        let mut field = self.name_encoding.encode(name, usize::MAX)?;
+        if field.len() > limit {
+            return Err(ArcError::FileNameTooLong(field.len(), limit));
+        }
+        field.resize(limit, 0);
+        Ok(field)
+    }
+}
+
+fn payload_size(payload: &[u8]) -> ArcResult<u32> {
+    u32::try_from(payload.len()).map_err(|_| ArcError::ArchiveTooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackOptions, pack_arc_raw};
+
+    fn entry_data(arc: &Arc, name: &str) -> Vec<u8> {
+        let idx = (0..arc.files_count())
+            .find(|&i| arc.get_file_name(i).unwrap() == name)
+            .unwrap_or_else(|| panic!("{name} missing"));
+        arc.get_file_data(idx).unwrap()
+    }
+
+    #[test]
+    fn test_edit_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        let options = PackOptions {
+            version: Some(ArcVersion::V1),
+            ..PackOptions::default()
+        };
+        pack_arc_raw(
+            [
+                (b"first".to_vec(), vec![1u8; 40]),
+                (b"second".to_vec(), vec![2u8; 100]),
+                (b"third".to_vec(), vec![3u8; 100]),
+            ],
+            &arc_path,
+            &options,
+        )
+        .unwrap();
+        let third_start = Arc::open(&arc_path).unwrap().into_parts().1 + 140;
+
+        let mut editor = ArcEditor::open(&arc_path).unwrap();
+        editor.replace_encoded("second", &[4u8; 10]).unwrap();
+        editor.replace_encoded("first", &[5u8; 80]).unwrap(); // appended
+        // Growing the index by three entries (96 bytes) overlaps the first
+        // 96 data bytes, so "second" no longer fits in place either.
+        for name in ["a", "b", "c"] {
+            editor.add_encoded(name, name.as_bytes()).unwrap();
+        }
+        editor.remove("a").unwrap();
+        editor.rename("b", "renamed").unwrap();
+        assert!(matches!(
+            editor.add_encoded("third", b""),
+            Err(ArcError::DuplicateEntry(_))
+        ));
+        assert!(matches!(
+            editor.remove("missing"),
+            Err(ArcError::EntryNotFound(_))
+        ));
+        editor.commit().unwrap();
+
+        let arc = Arc::open(&arc_path).unwrap();
+        assert_eq!(arc.files_count(), 5);
+        assert_eq!(entry_data(&arc, "first"), vec![5u8; 80]);
+        assert_eq!(entry_data(&arc, "second"), vec![4u8; 10]);
+        assert_eq!(entry_data(&arc, "third"), vec![3u8; 100]);
+        assert_eq!(entry_data(&arc, "renamed"), b"b");
+        assert_eq!(entry_data(&arc, "c"), b"c");
+
+        // The untouched entry did not move on disk.
+        let (_, data_offset, files) = arc.into_parts();
+        let third = files.iter().find(|f| f.name.starts_with(b"third")).unwrap();
+        assert_eq!(data_offset + u64::from(third.offset), third_start);
+    }
+
+    #[test]
+    fn test_edit_rejects_long_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        let options = PackOptions {
+            version: Some(ArcVersion::V1),
+            ..PackOptions::default()
+        };
+        pack_arc_raw([(b"first".to_vec(), vec![1u8; 4])], &arc_path, &options).unwrap();
+
+        // V1 names hold 16 bytes; longer ones are not truncated into a
+        // collision with each other.
+        let mut editor = ArcEditor::open(&arc_path).unwrap();
+        for name in ["sixteen_bytes_ok", "seventeen_bytes_1", "seventeen_bytes_2"] {
+            let result = editor.add_encoded(name, b"data");
+            if name.len() <= 16 {
+                result.unwrap();
+            } else {
+                assert!(matches!(result, Err(ArcError::FileNameTooLong(17, 16))));
+            }
+        }
+        assert!(matches!(
+            editor.rename("first", "seventeen_bytes_1"),
+            Err(ArcError::FileNameTooLong(17, 16))
+        ));
+        assert!(matches!(
+            editor.remove("sixteen_bytes_ok_"),
+            Err(ArcError::EntryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_edit_keeps_index_padding() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        let file = |name: &[u8], offset, padding: &[u8]| {
+            let mut field = name.to_vec();
+            field.resize(ArcVersion::V1.name_len(), 0);
+            ArcFile {
+                name: field,
+                offset,
+                size: 4,
+                padding: padding.to_vec(),
+            }
+        };
+        let mut original = Vec::new();
+        arc::write_index(
+            &mut original,
+            ArcVersion::V1,
+            &[file(b"first", 0, &[1, 2, 3]), file(b"second", 4, &[0, 9])],
+        )
+        .unwrap();
+        original.extend_from_slice(b"AAAABBBB");
+        std::fs::write(&arc_path, &original).unwrap();
+
+        let mut editor = ArcEditor::open(&arc_path).unwrap();
+        editor.rename("first", "renamed").unwrap();
+        editor.replace_encoded("second", b"CCCC").unwrap();
+        editor.add_encoded("third", b"DDDD").unwrap();
+        editor.commit().unwrap();
+
+        let (_, _, files) = Arc::open(&arc_path).unwrap().into_parts();
+        let paddings: Vec<_> = files.iter().map(|f| &f.padding[..3]).collect();
+        assert_eq!(paddings, [[1, 2, 3], [0, 9, 0], [0, 0, 0]]);
+    }
+
+    #[test]
+    fn test_edit_is_deferred_until_commit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        pack_arc_raw(
+            [
+                (b"first".to_vec(), vec![1u8; 40]),
+                (b"second".to_vec(), vec![2u8; 100]),
+            ],
+            &arc_path,
+            &PackOptions::default(),
+        )
+        .unwrap();
+        let original = std::fs::read(&arc_path).unwrap();
+
+        // A batch that fails partway leaves the archive as it was.
+        let mut editor = ArcEditor::open(&arc_path).unwrap();
+        editor.replace_encoded("second", &[4u8; 10]).unwrap();
+        editor.add_encoded("third", &[3u8; 10]).unwrap();
+        assert!(editor.replace_encoded("missing", &[0u8; 10]).is_err());
+        drop(editor);
+        assert_eq!(std::fs::read(&arc_path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_replace_keeps_shared_payload() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        let version = ArcVersion::V1;
+        let file = |name: &[u8]| {
+            let mut field = name.to_vec();
+            field.resize(version.name_len(), 0);
+            ArcFile {
+                name: field,
+                offset: 0,
+                size: 100,
+                padding: Vec::new(),
+            }
+        };
+        let mut data = Vec::new();
+        arc::write_index(&mut data, version, &[file(b"a"), file(b"b")]).unwrap();
+        data.extend_from_slice(&[7u8; 100]);
+        std::fs::write(&arc_path, data).unwrap();
+
+        let mut editor = ArcEditor::open(&arc_path).unwrap();
+        editor.replace_encoded("a", &[8u8; 10]).unwrap();
+        editor.commit().unwrap();
+
+        let arc = Arc::open(&arc_path).unwrap();
+        assert_eq!(entry_data(&arc, "a"), vec![8u8; 10]);
+        assert_eq!(entry_data(&arc, "b"), vec![7u8; 100]);
+        drop(arc);
+
+        // Once no other entry uses it, a smaller payload goes in place.
+        let len = std::fs::metadata(&arc_path).unwrap().len();
+        let mut editor = ArcEditor::open(&arc_path).unwrap();
+        editor.replace_encoded("b", &[9u8; 50]).unwrap();
+        editor.commit().unwrap();
+        assert_eq!(std::fs::metadata(&arc_path).unwrap().len(), len);
+        let arc = Arc::open(&arc_path).unwrap();
+        assert_eq!(entry_data(&arc, "a"), vec![8u8; 10]);
+        assert_eq!(entry_data(&arc, "b"), vec![9u8; 50]);
+    }
+}
@@ -2,6 +2,8 @@ use std::io;
 
 use thiserror::Error;
 
+use crate::arc::NameEncoding;
+
 #[derive(Error, Debug)]
 pub enum ArcError {
     #[error("IO error: {0}")]
@@ -13,12 +15,42 @@ pub enum ArcError {
     #[error("File index out of bounds: {0} >= {1}")]
     IndexOutOfBounds(u32, u32),
 
+    #[error("Header declares {0} entries, but the file only has room for {1}")]
+    TooManyEntries(u32, u64),
+
+    #[error("Entry {0} ('{1}'): {3} bytes at offset {2:#x} extend past the end of the archive")]
+    EntryOutOfBounds(u32, String, u64, u32),
+
     #[error("Invalid filename encoding: {0}")]
     InvalidFileName(#[from] std::str::Utf8Error),
 
+    #[error("File name cannot be encoded as {1}: {0}")]
+    UnencodableFileName(String, NameEncoding),
+
+    #[error("File name is {0} bytes, but the index only holds {1}")]
+    FileNameTooLong(usize, usize),
+
+    #[error("No entry named '{0}' in the archive")]
+    EntryNotFound(String),
+
+    #[error("An entry named '{0}' already exists in the archive")]
+    DuplicateEntry(String),
+
+    #[error("Archive data exceeds the 4 GiB addressable by the index")]
+    ArchiveTooLarge,
+
+    #[error("invalid name encoding '{0}', expected 'cp932', 'gbk' or 'utf8'")]
+    InvalidNameEncoding(String),
+
     #[error("BSE decryption failed")]
     BseDecryptError,
 
+    #[error("BSE needs at least 64 bytes of payload, got {0}")]
+    BseTooShort(usize),
+
+    #[error("invalid BSE version '{0}', expected '1.0' or '1.1'")]
+    InvalidBseVersion(String),
+
     #[error("DSC decryption failed")]
     DscDecryptError,
 
@@ -37,11 +69,25 @@ pub enum ArcError {
     #[error("PNG format unsupported: {0}")]
     PngUnsupported(&'static str),
 
+    #[error("The {0} codec cannot encode this asset")]
+    EncodeUnsupported(&'static str),
+
+    #[error("Entry is nested more than {0} wrapper layers deep")]
+    TooManyLayers(usize),
+
+    #[error("No codec named '{0}' is registered")]
+    UnknownCodec(String),
+
     #[error("Unsupported file type: {0}")]
     UnsupportedFileType(String),
 
-    #[error("invalid image format '{0}', expected 'bgi' or 'cbg'")]
+    #[error(
+        "invalid image format '{0}', expected 'bgi', 'bgi-scrambled', 'cbg', 'cbg2', 'dsc' or 'auto'"
+    )]
     InvalidImageFormat(String),
+
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
 }
 
 pub type ArcResult<T> = Result<T, ArcError>;
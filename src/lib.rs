@@ -10,21 +10,40 @@ pub mod arc;
 pub mod bgi;
 pub mod bse;
 pub mod cbg;
+pub mod codec;
 pub mod dsc;
+pub mod edit;
 pub mod error;
+pub mod manifest;
 pub mod ogg;
+pub mod salvage;
+pub mod verify;
 pub mod write;
 
+pub(crate) mod bits;
 pub(crate) mod decrypt;
 
-use std::{fmt, fs, io::Write, path::Path};
+use std::{
+    fmt, fs,
+    io::{self, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex, MutexGuard, PoisonError, mpsc},
+    thread,
+};
 
 use log::{debug, error, info, warn};
 use rayon::prelude::*;
 
 use crate::{
-    arc::ArcVersion,
+    arc::{ArcVersion, ArcWriter, NameEncoding},
+    bse::{BseRule, BseVersion},
+    codec::{CodecRegistry, EncodeParams},
     error::{ArcError, ArcResult},
+    manifest::{
+        Layer, MANIFEST_FILE, Manifest, ManifestEntry, PAYLOAD_FILE, RegionHash, SourceArchive,
+        StoredEntry, content_hash,
+    },
+    write::PixelLayout,
 };
 
 /// Image encoding format for packing PNG images into ARC archives.
@@ -33,15 +52,81 @@ pub enum ImageFormat {
     /// BGI uncompressed image format (default).
     #[default]
     Bgi,
+    /// BGI image with scrambled (`flag = 1`) snake-delta pixels.
+    BgiScrambled,
     /// `CompressedBG` V1 (Huffman + delta prediction) compressed format.
     CbgV1,
+    /// `CompressedBG` V2 (DCT + Huffman) lossy format; see
+    /// [`EncodeOptions::quality`].
+    CbgV2,
+    /// DSC-compressed raw image (`DSC FORMAT 1.00` around a 16-byte header).
+    Dsc,
+    /// Per image, the smallest of the lossless formats above (and
+    /// DSC-wrapped scrambled BGI) that decodes back to the source pixels.
+    Auto,
+}
+
+/// How source files are converted into ARC entries when packing or editing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Encoding applied to PNG images. `None` uses [`ImageFormat::default`],
+    /// except that packing with a manifest keeps each image's recorded
+    /// format; an explicit format re-encodes those images too.
+    pub image_format: Option<ImageFormat>,
+    /// Quality (1–100) of lossy image formats; higher is larger and closer to
+    /// the source.
+    pub quality: u8,
+    /// DSC-compress entries that are neither PNG images nor OGG audio.
+    pub dsc_compress: bool,
+    /// Write BGI images scrambled, including the BGI fallback of other
+    /// formats. [`ImageFormat::BgiScrambled`] implies it.
+    pub scramble_bgi: bool,
+    /// Key for the CBG V1 weight-table encryption. `None` derives one from
+    /// each image, so output is reproducible but keys differ between assets.
+    pub cbg_key: Option<u32>,
+    /// Store BGI and CBG images as 8 bpp grey. `None` does so for PNGs whose
+    /// pixels are all opaque grey; `Some(true)` converts any image to its
+    /// luma, dropping alpha; `Some(false)` always keeps 24/32 bpp.
+    pub grayscale: Option<bool>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            image_format: None,
+            quality: cbg::DEFAULT_QUALITY,
+            dsc_compress: false,
+            scramble_bgi: false,
+            cbg_key: None,
+            grayscale: None,
+        }
+    }
+}
+
+impl ImageFormat {
+    /// Short name accepted by `ImageFormat::try_from`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bgi => "bgi",
+            Self::BgiScrambled => "bgi-scrambled",
+            Self::CbgV1 => "cbg",
+            Self::CbgV2 => "cbg2",
+            Self::Dsc => "dsc",
+            Self::Auto => "auto",
+        }
+    }
 }
 
 impl fmt::Display for ImageFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Bgi => write!(f, "BGI"),
+            Self::BgiScrambled => write!(f, "BGI (scrambled)"),
             Self::CbgV1 => write!(f, "CBG V1"),
+            Self::CbgV2 => write!(f, "CBG V2"),
+            Self::Dsc => write!(f, "DSC"),
+            Self::Auto => write!(f, "auto"),
         }
     }
 }
@@ -52,108 +137,433 @@ impl TryFrom<&str> for ImageFormat {
     fn try_from(v: &str) -> Result<Self, Self::Error> {
         match v.to_ascii_lowercase().as_str() {
             "bgi" => Ok(Self::Bgi),
+            "bgi-scrambled" | "bgi1" => Ok(Self::BgiScrambled),
             "cbg" | "cbgv1" | "cbg-v1" | "cbg1" => Ok(Self::CbgV1),
+            "cbgv2" | "cbg-v2" | "cbg2" => Ok(Self::CbgV2),
+            "dsc" => Ok(Self::Dsc),
+            "auto" => Ok(Self::Auto),
             _ => Err(ArcError::InvalidImageFormat(v.to_owned())),
         }
     }
 }
 
+/// Content kind of an archive entry, as told apart by [`decode_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// DSC FORMAT 1.00 compressed data.
+    Dsc,
+    /// `CompressedBG` image.
+    Cbg,
+    /// BGI uncompressed image.
+    Bgi,
+    /// BGI-wrapped OGG Vorbis audio.
+    Audio,
+    /// Anything else (scripts, text, ...).
+    Raw,
+}
+
+impl EntryKind {
+    /// Number of leading entry bytes [`EntryKind::sniff`] needs.
+    pub const SNIFF_LEN: usize = 0x100;
+
+    /// Detect the kind from the first [`EntryKind::SNIFF_LEN`] bytes of an
+    /// entry (or the whole entry, if shorter).
+    ///
+    /// Returns the kind of the content inside a BSE wrapper, and whether the
+    /// wrapper was present.
+    #[must_use]
+    pub fn sniff(prefix: &[u8]) -> (Self, bool) {
+        let Ok(inner) = strip_bse(prefix) else {
+            return (Self::Raw, true);
+        };
+        let kind = if dsc::is_dsc(&inner) {
+            Self::Dsc
+        } else if cbg::is_cbg(&inner) {
+            Self::Cbg
+        } else if bgi::is_bgi(&inner) {
+            Self::Bgi
+        } else if ogg::is_bgi_ogg(&inner) {
+            Self::Audio
+        } else {
+            Self::Raw
+        };
+        (kind, bse::is_bse(prefix))
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dsc => write!(f, "DSC"),
+            Self::Cbg => write!(f, "CBG"),
+            Self::Bgi => write!(f, "BGI"),
+            Self::Audio => write!(f, "audio"),
+            Self::Raw => write!(f, "raw"),
+        }
+    }
+}
+
 /// Check whether the data starts with a PNG magic signature.
 fn is_png(data: &[u8]) -> bool {
     data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A])
 }
 
-/// Encode a single file for inclusion in an ARC archive.
+/// Encode a single file for inclusion in an ARC archive as the entry `name`,
+/// with the codecs of `codecs`.
 ///
 /// - **OGG** → BGI-wrapped audio (`bw  ` header)
-/// - **PNG** → image encoded with the given [`ImageFormat`]; CBG V1 falls back
-///   to BGI on the rare occasion that Huffman code lengths are pathological.
-/// - **Other** → passed through as-is (scripts, text, etc.)
-fn encode_for_pack(data: &[u8], format: ImageFormat) -> ArcResult<Vec<u8>> {
+/// - **PNG** → image encoded with [`EncodeOptions::image_format`]; CBG falls
+///   back to BGI on the rare occasion that Huffman code lengths are
+///   pathological. [`ImageFormat::Auto`] also returns the encoding it
+///   picked.
+/// - **Other** → DSC-compressed if [`EncodeOptions::dsc_compress`] is set,
+///   otherwise passed through as-is (scripts, text, etc.)
+pub(crate) fn encode_for_pack(
+    name: &str,
+    data: &[u8],
+    options: EncodeOptions,
+    codecs: &CodecRegistry,
+) -> ArcResult<(Vec<u8>, Option<AutoChoice>)> {
+    let format = options.image_format.unwrap_or_default();
     if ogg::is_ogg(data) {
-        Ok(ogg::add_header(data))
+        let audio = DecodedAsset::Audio {
+            ogg: data.to_vec(),
+            header: Vec::new(),
+        };
+        Ok((
+            codecs.encode("bw-ogg", &audio, EncodeParams::default())?,
+            None,
+        ))
     } else if is_png(data) {
         let img = write::read_png(data)?;
+        let layout = match options.grayscale {
+            Some(true) => PixelLayout::Gray,
+            Some(false) if img.has_alpha => PixelLayout::Bgra,
+            Some(false) => PixelLayout::Bgr,
+            None => PixelLayout::detect(&img.rgba),
+        };
         debug!(
-            "PNG: {}x{}, {}, {} bytes → {format}",
+            "PNG: {}x{}, {}bpp, {} bytes → {format}",
             img.width,
             img.height,
-            if img.has_alpha { "32bpp" } else { "24bpp" },
+            layout.bpp(),
             data.len(),
         );
-        match format {
-            ImageFormat::Bgi => Ok(bgi::encode_bgi(
-                &img.rgba,
-                img.width,
-                img.height,
-                img.has_alpha,
-            )),
-            ImageFormat::CbgV1 => {
-                match cbg::encode_cbg_v1(&img.rgba, img.width, img.height, img.has_alpha) {
-                    Ok(cbg_data) => Ok(cbg_data),
-                    Err(e) => {
-                        debug!("CBG V1 encode failed ({e:?}), falling back to BGI uncompressed");
-                        Ok(bgi::encode_bgi(
-                            &img.rgba,
-                            img.width,
-                            img.height,
-                            img.has_alpha,
-                        ))
-                    }
-                }
-            }
+        let image = png_asset(img);
+        if format == ImageFormat::Auto {
+            let (choice, encoded) = encode_image_auto(&image, layout, options, codecs)?;
+            info!("{name}: auto image format {choice}");
+            return Ok((encoded, Some(choice)));
         }
+        Ok((encode_image(&image, layout, format, options, codecs)?, None))
+    } else if options.dsc_compress {
+        debug!("unknown file type, DSC-compressing {} bytes", data.len());
+        let raw = DecodedAsset::Raw(data.to_vec());
+        Ok((codecs.encode("dsc", &raw, EncodeParams::default())?, None))
     } else {
         debug!("unknown file type, passing through as-is");
-        Ok(data.to_vec())
+        Ok((data.to_vec(), None))
     }
 }
 
-/// Decode a single file extracted from an ARC archive.
+/// A PNG read for packing, as the image asset codecs encode.
+fn png_asset(img: write::PngImage) -> DecodedAsset {
+    DecodedAsset::Image {
+        rgba: img.rgba,
+        width: img.width,
+        height: img.height,
+        source_format: SourceFormat::Png,
+    }
+}
+
+/// Encode an image asset as `format` with the codec that writes it, falling
+/// back to plain (or scrambled) BGI if that fails.
+fn encode_image(
+    image: &DecodedAsset,
+    layout: PixelLayout,
+    format: ImageFormat,
+    options: EncodeOptions,
+    codecs: &CodecRegistry,
+) -> ArcResult<Vec<u8>> {
+    let Some((codec, mut params)) = image_codec(format, layout, options) else {
+        return encode_image_auto(image, layout, options, codecs).map(|(_, encoded)| encoded);
+    };
+    params.scramble |= options.scramble_bgi;
+    if codec == "bgi" {
+        return codecs.encode(codec, image, params);
+    }
+    codecs.encode(codec, image, params).or_else(|e| {
+        debug!("{format} encode failed ({e:?}), falling back to BGI uncompressed");
+        codecs.encode("bgi", image, params)
+    })
+}
+
+/// The codec that writes `format` and its parameters, or `None` for
+/// [`ImageFormat::Auto`]. Scrambling follows `format` alone.
+fn image_codec(
+    format: ImageFormat,
+    layout: PixelLayout,
+    options: EncodeOptions,
+) -> Option<(&'static str, EncodeParams)> {
+    let mut params = EncodeParams {
+        layout: Some(layout),
+        scramble: format == ImageFormat::BgiScrambled,
+        ..EncodeParams::default()
+    };
+    let codec = match format {
+        ImageFormat::Auto => return None,
+        ImageFormat::Bgi | ImageFormat::BgiScrambled => "bgi",
+        ImageFormat::CbgV1 => {
+            params.cbg_key = options.cbg_key;
+            "cbg"
+        }
+        ImageFormat::CbgV2 => {
+            params.cbg_quality = Some(options.quality);
+            "cbg"
+        }
+        ImageFormat::Dsc => "dsc",
+    };
+    Some((codec, params))
+}
+
+/// Lossless encodings tried by [`ImageFormat::Auto`], as `(format,
+/// DSC-wrapped)`. Earlier entries win ties.
+const AUTO_CANDIDATES: [(ImageFormat, bool); 5] = [
+    (ImageFormat::Bgi, false),
+    (ImageFormat::BgiScrambled, false),
+    (ImageFormat::CbgV1, false),
+    (ImageFormat::Dsc, false),
+    (ImageFormat::BgiScrambled, true),
+];
+
+/// An encoding picked by [`ImageFormat::Auto`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoChoice {
+    /// Image format of the stored payload.
+    pub format: ImageFormat,
+    /// Whether the image was DSC-compressed on top.
+    pub dsc_wrapped: bool,
+    /// Payload size in bytes.
+    pub size: usize,
+}
+
+impl fmt::Display for AutoChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.dsc_wrapped {
+            write!(f, "DSC-wrapped ")?;
+        }
+        write!(f, "{}, {} bytes", self.format, self.size)
+    }
+}
+
+/// Encode `image` with every [`AUTO_CANDIDATES`] entry and keep the smallest
+/// output that decodes back to the pixels `layout` stores.
+fn encode_image_auto(
+    image: &DecodedAsset,
+    layout: PixelLayout,
+    options: EncodeOptions,
+    codecs: &CodecRegistry,
+) -> ArcResult<(AutoChoice, Vec<u8>)> {
+    let DecodedAsset::Image {
+        rgba,
+        width,
+        height,
+        ..
+    } = image
+    else {
+        return Err(ArcError::EncodeUnsupported("auto"));
+    };
+    let expected = write::convert_bgr_to_rgba(
+        &layout.pack(rgba),
+        usize::from(*width),
+        usize::from(*height),
+        u32::from(layout.bpp()),
+    );
+    let round_trips = |encoded: &[u8]| {
+        matches!(
+            codecs.decode(encoded),
+            Ok(DecodedAsset::Image { rgba, .. }) if rgba == expected
+        )
+    };
+
+    let (format, dsc_wrapped, encoded) = AUTO_CANDIDATES
+        .par_iter()
+        .filter_map(|&(format, dsc_wrapped)| {
+            // No BGI fallback here: a failed candidate is skipped, not
+            // reported under its own format.
+            let (codec, params) = image_codec(format, layout, options)?;
+            let mut encoded = codecs.encode(codec, image, params).ok()?;
+            if dsc_wrapped {
+                let raw = DecodedAsset::Raw(encoded);
+                encoded = codecs.encode("dsc", &raw, EncodeParams::default()).ok()?;
+            }
+            round_trips(&encoded).then_some((format, dsc_wrapped, encoded))
+        })
+        .min_by_key(|(_, _, encoded)| encoded.len())
+        .ok_or(ArcError::EncodeUnsupported("auto"))?;
+    let choice = AutoChoice {
+        format,
+        dsc_wrapped,
+        size: encoded.len(),
+    };
+    Ok((choice, encoded))
+}
+
+/// Container an image was stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceFormat {
+    /// BGI uncompressed image.
+    Bgi,
+    /// `CompressedBG` V1 or V2 image.
+    Cbg,
+    /// Raw image inside DSC FORMAT 1.00 compressed data.
+    Dsc,
+    /// PNG file read for packing.
+    Png,
+}
+
+/// An archive entry decoded in memory by [`decode_bytes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedAsset {
+    /// An image as RGBA8 pixels.
+    Image {
+        rgba: Vec<u8>,
+        width: u16,
+        height: u16,
+        source_format: SourceFormat,
+    },
+    /// OGG Vorbis audio split from its BGI wrapper header.
+    Audio { ogg: Vec<u8>, header: Vec<u8> },
+    /// A BGI compiled script (`BurikoCompiledScriptVer1.00`).
+    Script(Vec<u8>),
+    /// Anything else, after BSE/DSC unwrapping.
+    Raw(Vec<u8>),
+}
+
+impl DecodedAsset {
+    /// Extension [`decode_file`] gives the output file, if it replaces the
+    /// entry's own.
+    #[must_use]
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::Image { .. } => Some("png"),
+            Self::Audio { .. } => Some("ogg"),
+            Self::Script(_) | Self::Raw(_) => None,
+        }
+    }
+}
+
+/// Signature at the start of BGI compiled scripts.
+const SCRIPT_MAGIC: &[u8] = b"BurikoCompiledScriptVer1.00\0";
+
+/// Decode a single file extracted from an ARC archive in memory.
 ///
 /// Automatically detects and handles:
 /// - BSE stream encryption (transparently decrypted)
-/// - DSC FORMAT 1.00 compressed data (→ PNG if image, else raw)
-/// - `CompressedBG` (CBG) V1/V2 images (→ PNG)
-/// - BGI uncompressed images (→ PNG)
-/// - BGI-wrapped OGG Vorbis audio (→ OGG)
-/// - Unrecognized data (written as-is)
-pub fn decode_file(data: &[u8], output_path: impl AsRef<Path>) -> ArcResult<()> {
-    // BSE wraps the inner file.  Only the 0x40-byte header at offsets 0x10..0x4F
-    // is encrypted; the body (from 0x50) is plaintext.
-    // After stripping the 0x10-byte BSE metadata, the inner payload is:
-    //   decrypted_header (0x40 bytes) + body (rest)
-    let bse_owned;
-    let inner: &[u8] = if bse::is_bse(data) {
-        let mut payload = data.to_vec();
-        bse::decrypt_bse(&mut payload)?;
-        bse_owned = payload;
-        &bse_owned[0x10..]
+/// - DSC FORMAT 1.00 compressed data (→ image if it holds a raw image, else
+///   the decompressed bytes are decoded again, so DSC-wrapped BGI/CBG images
+///   and audio come out decoded)
+/// - `CompressedBG` (CBG) V1/V2 images
+/// - BGI uncompressed images
+/// - BGI-wrapped OGG Vorbis audio
+/// - Unrecognized data (returned as-is)
+///
+/// This is [`CodecRegistry::decode`] on the [built-in
+/// codecs](CodecRegistry::builtin).
+pub fn decode_bytes(data: &[u8]) -> ArcResult<DecodedAsset> {
+    CodecRegistry::builtin().decode(data)
+}
+
+/// Classify undecoded bytes as a script or raw data.
+pub(crate) fn bytes_asset(data: Vec<u8>) -> DecodedAsset {
+    if data.starts_with(SCRIPT_MAGIC) {
+        DecodedAsset::Script(data)
     } else {
-        data
+        DecodedAsset::Raw(data)
+    }
+}
+
+/// Decode a single file extracted from an ARC archive and write the result.
+///
+/// Images are written as PNG and audio as OGG, replacing the extension of
+/// `output_path`; scripts and raw data are written to `output_path` as-is.
+/// See [`decode_bytes`] for the formats handled.
+pub fn decode_file(data: &[u8], output_path: impl AsRef<Path>) -> ArcResult<()> {
+    write_asset(decode_bytes(data)?, output_path).map(drop)
+}
+
+/// Write a decoded asset the way [`decode_file`] does, returning the path
+/// written and the [`content_hash`] of the bytes written there.
+fn write_asset(asset: DecodedAsset, output_path: impl AsRef<Path>) -> ArcResult<(PathBuf, u64)> {
+    let output_path = match asset.extension() {
+        Some(ext) => output_path.as_ref().with_extension(ext),
+        None => output_path.as_ref().to_path_buf(),
+    };
+
+    let data = match asset {
+        DecodedAsset::Image {
+            rgba,
+            width,
+            height,
+            ..
+        } => write::rgba_to_png(width, height, &rgba)?,
+        DecodedAsset::Audio { ogg: data, .. }
+        | DecodedAsset::Script(data)
+        | DecodedAsset::Raw(data) => data,
     };
+    let hash = content_hash(&data);
+    fs::write(&output_path, data)?;
+    Ok((output_path, hash))
+}
 
-    if dsc::is_dsc(inner) {
-        debug!("DSC...");
-        let (decrypted, size) = dsc::decrypt_dsc(inner)?;
-        dsc::save(&decrypted, size, output_path)?;
-    } else if cbg::is_cbg(inner) {
-        let (decrypted, w, h) = cbg::decrypt_cbg(inner)?;
-        cbg::save(&decrypted, w, h, output_path)?;
-    } else if bgi::is_bgi(inner) {
-        let (decrypted, w, h) = bgi::decrypt_bgi(inner)?;
-        bgi::save(&decrypted, w, h, output_path)?;
-    } else if ogg::is_bgi_ogg(inner) {
-        debug!("OGG...");
-        let header_removed = ogg::remove_header(inner);
-        ogg::save(&header_removed, output_path)?;
+/// Default cap on entry bytes held in memory by [`unpack_arc_with`].
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 256 * 1024 * 1024;
+
+/// Decrypt and strip a BSE wrapper, if present.
+fn strip_bse(data: &[u8]) -> ArcResult<std::borrow::Cow<'_, [u8]>> {
+    if bse::is_bse(data) {
+        Ok(bse::strip(data)?.into())
     } else {
-        debug!("uncompressed...");
-        let mut file = fs::File::create(output_path.as_ref())?;
-        file.write_all(inner)?;
+        Ok(data.into())
     }
+}
 
-    Ok(())
+/// Options for [`unpack_arc_with`].
+#[derive(Debug, Clone)]
+pub struct UnpackOptions {
+    /// Charset used to decode entry names into output file names.
+    pub name_encoding: NameEncoding,
+    /// Maximum number of raw entry bytes read but not yet decoded and written.
+    ///
+    /// A single entry larger than this is still processed, on its own.
+    pub max_in_flight: usize,
+    /// Open the archive with [`arc::Arc::open_salvage`], extracting whatever
+    /// survived in a truncated or corrupted archive.
+    pub salvage: bool,
+    /// Codecs used to decode entries.
+    pub codecs: CodecRegistry,
+    /// Write a [`manifest::MANIFEST_FILE`] recording how each entry was
+    /// stored and where the archive is, for [`pack_arc_with`] to rebuild it.
+    /// Salvaged archives only get the format chains.
+    pub manifest: bool,
+    /// Also copy the archive's data region into [`manifest::PAYLOAD_FILE`],
+    /// so the archive can still be rebuilt once it is moved or modified.
+    /// This takes as much disk space as the archive itself; without the copy,
+    /// [`pack_arc_with`] fails once the source archive has changed.
+    pub copy_payloads: bool,
+}
+
+impl Default for UnpackOptions {
+    fn default() -> Self {
+        Self {
+            name_encoding: NameEncoding::default(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            salvage: false,
+            codecs: CodecRegistry::builtin().clone(),
+            manifest: true,
+            copy_payloads: true,
+        }
+    }
 }
 
 /// Unpack all entries from an ARC archive into a directory.
@@ -163,7 +573,22 @@ pub fn unpack_arc(
     arc_path: impl AsRef<Path>,
     output_dir: impl AsRef<Path>,
 ) -> ArcResult<Vec<(String, ArcResult<()>)>> {
-    let mut arc = crate::arc::Arc::open(arc_path.as_ref())?;
+    unpack_arc_with(arc_path, output_dir, &UnpackOptions::default())
+}
+
+/// Unpack all entries from an ARC archive into a directory with explicit
+/// [`UnpackOptions`].
+///
+/// Reading, decoding and writing overlap: one thread reads entries in data
+/// order while rayon workers decode and write them, and the reader waits
+/// whenever [`UnpackOptions::max_in_flight`] bytes are outstanding. Peak
+/// memory is therefore bounded by the cap rather than the archive size.
+pub fn unpack_arc_with(
+    arc_path: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    options: &UnpackOptions,
+) -> ArcResult<Vec<(String, ArcResult<()>)>> {
+    let arc = open_for_unpack(arc_path.as_ref(), options)?;
     let count = arc.files_count();
     let out_dir = output_dir.as_ref();
 
@@ -173,11 +598,119 @@ pub fn unpack_arc(
 
     info!("File count: {count}");
 
-    // Phase 1: sequential I/O — read each file's raw data into memory.
-    // We read sequentially (no concurrent seek+read) to avoid data races on
-    // the shared file descriptor, then decode in parallel.
-    let mut file_infos: Vec<(String, ArcResult<Vec<u8>>)> = Vec::with_capacity(count as usize);
-    for i in 0..count {
+    // A salvaged index does not describe the input file, so it cannot be
+    // rebuilt from the manifest.
+    let rebuildable = !options.salvage;
+    let stored =
+        |i: u32, hash| rebuildable.then(|| StoredEntry::new(&arc.files()[i as usize], hash));
+
+    let budget = InFlight::new(options.max_in_flight);
+    let (tx, rx) = mpsc::channel();
+
+    let hash_region = options.manifest && rebuildable;
+    let (mut results, region_hash) = thread::scope(|scope| {
+        let reader = scope.spawn(|| read_entries(&arc, &budget, tx, hash_region));
+
+        // Stop the reader however the workers finish; a panicking decoder
+        // would otherwise leave it waiting for budget forever.
+        let _cancel = CancelOnDrop(&budget);
+        let results = rx
+            .into_iter()
+            .par_bridge()
+            .map(|(i, file_name, permit, data)| {
+                let result = data.and_then(|data| {
+                    info!("Extracting {file_name}");
+                    let (asset, chain) = options.codecs.decode_layers(&data)?;
+                    let (path, hash) = write_asset(asset, out_dir.join(&file_name))?;
+                    let file = path.strip_prefix(out_dir).unwrap_or(&path);
+                    Ok(ManifestEntry {
+                        file: file.to_string_lossy().into_owned(),
+                        chain,
+                        stored: stored(i, Some(hash)),
+                    })
+                });
+                drop(permit);
+                if let Err(ref e) = result {
+                    error!("Failed to process file {file_name}: {e}");
+                }
+                (i, file_name, result)
+            })
+            .collect::<Vec<(u32, String, ArcResult<ManifestEntry>)>>();
+        let region_hash = reader
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (results, region_hash)
+    });
+    debug!("Peak in-flight entry bytes: {}", budget.peak());
+
+    results.sort_unstable_by_key(|(i, _, _)| *i);
+    let mut manifest = Manifest {
+        version: rebuildable.then(|| arc.version()),
+        ..Manifest::default()
+    };
+    let results = results
+        .into_iter()
+        .map(|(i, file_name, result)| {
+            let result = result
+                .inspect_err(|_| {
+                    // Not extracted, but its payload is still kept on rebuild.
+                    let stored = stored(i, None).map(|stored| ManifestEntry {
+                        file: file_name.clone(),
+                        chain: Vec::new(),
+                        stored: Some(stored),
+                    });
+                    manifest.entries.extend(stored);
+                })
+                .map(|entry| manifest.entries.push(entry));
+            (file_name, result)
+        })
+        .collect();
+    if options.manifest {
+        if rebuildable {
+            manifest.source = match region_hash.map(|hash| {
+                SourceArchive::record(
+                    arc_path.as_ref(),
+                    arc.data_offset(),
+                    arc.stream_end() - arc.data_offset(),
+                    hash,
+                )
+            }) {
+                Some(Ok(source)) => Some(source),
+                Some(Err(ArcError::InvalidManifest(e))) => {
+                    warn!("{e}; pack can only rebuild the archive from a payload copy");
+                    None
+                }
+                Some(Err(e)) => return Err(e),
+                None => None,
+            };
+            if options.copy_payloads {
+                copy_data_region(arc_path.as_ref(), arc.data_offset(), out_dir)?;
+            }
+        }
+        manifest.write(out_dir)?;
+    }
+
+    Ok(results)
+}
+
+/// Producer of [`unpack_arc_with`]: read every entry of `arc` in data order
+/// under `budget` and send it to the decode workers.
+///
+/// Reading in data order keeps the access pattern linear and lets the data
+/// region be hashed along the way for the manifest's source line. Returns
+/// that hash if `hash_region` is set and every byte was read.
+fn read_entries<'a>(
+    arc: &arc::Arc<fs::File>,
+    budget: &'a InFlight,
+    tx: mpsc::Sender<(u32, String, Permit<'a>, ArcResult<Vec<u8>>)>,
+    hash_region: bool,
+) -> Option<u64> {
+    let read_region = |pos, buf: &mut [u8]| arc.read_at(arc.data_offset() + pos, buf);
+    let mut order: Vec<u32> = (0..arc.files_count()).collect();
+    order.sort_by_key(|&i| (arc.files()[i as usize].offset, i));
+
+    let mut region = hash_region.then(RegionHash::new);
+    for i in order {
         let file_name = match arc.get_file_name(i) {
             Ok(n) => n.to_string(),
             Err(e) => {
@@ -185,37 +718,293 @@ pub fn unpack_arc(
                 format!("<index {i}>")
             }
         };
+        let size = arc.get_file_size(i).map_or(0, |s| s as usize);
+        let permit = budget.acquire(size)?;
         let data = arc.get_file_data(i);
-        if let Err(ref e) = data {
-            error!("Failed to read data for {file_name}: {e}");
+        match &data {
+            Ok(data) => {
+                let offset = u64::from(arc.files()[i as usize].offset);
+                if let Some(Err(e)) = region
+                    .as_mut()
+                    .map(|region| region.add(offset, data, read_region))
+                {
+                    warn!("Failed to hash the data region: {e}");
+                    region = None;
+                }
+            }
+            Err(e) => {
+                error!("Failed to read data for {file_name}: {e}");
+                region = None;
+            }
         }
-        file_infos.push((file_name, data));
+        tx.send((i, file_name, permit, data)).ok()?;
     }
+    drop(tx);
+    region?
+        .finish(arc.stream_end() - arc.data_offset(), read_region)
+        .inspect_err(|e| warn!("Failed to hash the data region: {e}"))
+        .ok()
+}
 
-    let results: Vec<(String, ArcResult<()>)> = file_infos
-        .into_par_iter()
-        .map(|(file_name, data)| {
-            let data = match data {
-                Ok(d) => d,
-                Err(e) => return (file_name, Err(e)),
-            };
-            info!("Extracting {file_name}");
-            let result = decode_file(&data, out_dir.join(&file_name));
-            if let Err(ref e) = result {
-                error!("Failed to process file {file_name}: {e}");
+/// Open the archive for [`unpack_arc_with`], salvaging it if requested.
+fn open_for_unpack(arc_path: &Path, options: &UnpackOptions) -> ArcResult<arc::Arc<fs::File>> {
+    let arc = if options.salvage {
+        let (arc, report) = arc::Arc::open_salvage(arc_path)?;
+        info!(
+            "salvaged {} of {} entries, recovered {} orphaned payloads",
+            report.detected.unwrap_or(report.declared)
+                - report.unreadable
+                - report.skipped.len() as u32,
+            report.detected.unwrap_or(report.declared),
+            report.recovered
+        );
+        arc
+    } else {
+        arc::Arc::open(arc_path)?
+    };
+    Ok(arc.with_name_encoding(options.name_encoding))
+}
+
+/// Copy the data region of the archive at `arc_path`, from `data_offset` to
+/// the end, into the [`manifest::PAYLOAD_FILE`] of `out_dir`.
+fn copy_data_region(arc_path: &Path, data_offset: u64, out_dir: &Path) -> ArcResult<()> {
+    let mut source = fs::File::open(arc_path)?;
+    source.seek(SeekFrom::Start(data_offset))?;
+    io::copy(
+        &mut source,
+        &mut fs::File::create(out_dir.join(PAYLOAD_FILE))?,
+    )?;
+    Ok(())
+}
+
+/// Byte budget shared by the reader thread and the decode workers of
+/// [`unpack_arc_with`].
+struct InFlight {
+    state: Mutex<Budget>,
+    freed: Condvar,
+    limit: usize,
+}
+
+#[derive(Default)]
+struct Budget {
+    used: usize,
+    /// Highest `used` seen, for checking the cap.
+    peak: usize,
+    /// Set once the consumer stops; pending and later acquires fail.
+    cancelled: bool,
+}
+
+impl InFlight {
+    fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(Budget::default()),
+            freed: Condvar::new(),
+            limit,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Budget> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Block until `n` more bytes fit under the limit. An oversized request is
+    /// admitted once nothing else is in flight, so it cannot stall forever.
+    ///
+    /// Returns `None` once [`InFlight::cancel`] was called. The bytes are
+    /// released when the returned [`Permit`] is dropped, including during a
+    /// panic.
+    fn acquire(&self, n: usize) -> Option<Permit<'_>> {
+        let mut state = self.lock();
+        while !state.cancelled && state.used > 0 && state.used + n > self.limit {
+            state = self
+                .freed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if state.cancelled {
+            return None;
+        }
+        state.used += n;
+        state.peak = state.peak.max(state.used);
+        Some(Permit { budget: self, n })
+    }
+
+    /// Wake and fail every pending and future [`InFlight::acquire`].
+    fn cancel(&self) {
+        self.lock().cancelled = true;
+        self.freed.notify_all();
+    }
+
+    fn peak(&self) -> usize {
+        self.lock().peak
+    }
+}
+
+/// Bytes held under an [`InFlight`] budget until dropped.
+struct Permit<'a> {
+    budget: &'a InFlight,
+    n: usize,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.budget.lock().used -= self.n;
+        self.budget.freed.notify_all();
+    }
+}
+
+/// Cancels an [`InFlight`] budget when dropped, so the reader thread of
+/// [`unpack_arc_with`] stops even if the consumer unwinds.
+struct CancelOnDrop<'a>(&'a InFlight);
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// Options for [`pack_arc_with`] and [`pack_arc_raw`].
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    /// ARC format version to write. `None` keeps the version recorded in the
+    /// input directory's manifest, or writes [`ArcVersion::V2`] if there is
+    /// none.
+    pub version: Option<ArcVersion>,
+    /// How source files are converted into entries.
+    pub encode: EncodeOptions,
+    /// Charset used to encode UTF-8 file names into the index.
+    pub name_encoding: NameEncoding,
+    /// Entries to wrap in BSE after conversion; the first matching rule
+    /// picks the BSE version.
+    pub bse: Vec<BseRule>,
+    /// Rebuild the archive an input directory was unpacked from, following
+    /// its [`manifest::MANIFEST_FILE`]; files it lists are re-encoded with
+    /// their recorded format chain instead of [`PackOptions::encode`].
+    pub manifest: bool,
+    /// Codecs used to encode entries, including the [`Layer::Codec`] layers
+    /// of a manifest.
+    pub codecs: CodecRegistry,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            version: None,
+            encode: EncodeOptions::default(),
+            name_encoding: NameEncoding::default(),
+            bse: Vec::new(),
+            manifest: true,
+            codecs: CodecRegistry::builtin().clone(),
+        }
+    }
+}
+
+impl PackOptions {
+    /// Convert `data` for the entry `name`, with its manifest `chain` if it
+    /// has one, then BSE-wrap it if a rule matches. An image encoded with
+    /// [`ImageFormat::Auto`] is recorded in `report`.
+    fn encode_entry(
+        &self,
+        name: &str,
+        data: &[u8],
+        chain: Option<&[Layer]>,
+        report: &mut PackReport,
+    ) -> ArcResult<Vec<u8>> {
+        let (encoded, choice) = match chain {
+            Some(chain) => self.encode_chain(name, data, chain)?,
+            None => encode_for_pack(name, data, self.encode, &self.codecs)?,
+        };
+        if let Some(choice) = choice {
+            report.auto_formats.push((name.to_owned(), choice));
+        }
+        match self.bse.iter().find(|rule| rule.matches(name)) {
+            Some(rule) if !bse::is_bse(&encoded) => wrap_bse(name, encoded, rule.version),
+            _ => Ok(encoded),
+        }
+    }
+
+    /// Re-encode `data` with the layers of a manifest chain, innermost first.
+    ///
+    /// Images keep their recorded format and pixel layout unless an edit no
+    /// longer fits the layout; an image with an explicit
+    /// [`EncodeOptions::image_format`] and a file whose type changed are
+    /// encoded like new ones.
+    fn encode_chain(
+        &self,
+        name: &str,
+        data: &[u8],
+        chain: &[Layer],
+    ) -> ArcResult<(Vec<u8>, Option<AutoChoice>)> {
+        let (wrappers, mut encoded) = match chain.split_last() {
+            Some((Layer::Image(..), _)) if is_png(data) && self.encode.image_format.is_some() => {
+                return encode_for_pack(name, data, self.encode, &self.codecs);
             }
-            (file_name, result)
-        })
-        .collect();
+            Some((Layer::Image(format, layout), rest)) if is_png(data) => {
+                let img = write::read_png(data)?;
+                let layout = match layout {
+                    PixelLayout::Gray if !img.grayscale => PixelLayout::detect(&img.rgba),
+                    PixelLayout::Bgr if img.has_alpha => PixelLayout::Bgra,
+                    _ => *layout,
+                };
+                let image = png_asset(img);
+                (
+                    rest,
+                    encode_image(&image, layout, *format, self.encode, &self.codecs)?,
+                )
+            }
+            Some((Layer::Audio, rest)) if ogg::is_ogg(data) => (
+                rest,
+                encode_for_pack(name, data, self.encode, &self.codecs)?.0,
+            ),
+            Some((last, _)) if last.is_content() => {
+                warn!("{name} no longer matches its recorded {last} layer, encoding it anew");
+                return encode_for_pack(name, data, self.encode, &self.codecs);
+            }
+            _ => (chain, data.to_vec()),
+        };
+        for layer in wrappers.iter().rev() {
+            encoded = match layer {
+                Layer::Bse(version) => wrap_bse(name, encoded, *version)?,
+                Layer::Dsc => dsc::encode_dsc(&encoded)?,
+                Layer::Codec(codec) => {
+                    self.codecs
+                        .encode(codec, &bytes_asset(encoded), EncodeParams::default())?
+                }
+                Layer::Image(..) | Layer::Audio => {
+                    return Err(ArcError::InvalidManifest(format!(
+                        "{name}: {layer} is not the last layer"
+                    )));
+                }
+            };
+        }
+        Ok((encoded, None))
+    }
+}
 
-    Ok(results)
+/// Wrap the converted entry `name` in BSE; data too short for a BSE header is
+/// stored unwrapped.
+fn wrap_bse(name: &str, encoded: Vec<u8>, version: BseVersion) -> ArcResult<Vec<u8>> {
+    if encoded.len() < 0x40 {
+        warn!("{name} is too short for BSE, storing it unwrapped");
+        return Ok(encoded);
+    }
+    debug!("{name}: wrapping in {version}");
+    bse::encrypt_bse(&encoded, version, decrypt::content_key(&encoded))
+}
+
+/// Summary of a [`pack_arc_with`] or [`pack_arc_raw`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackReport {
+    /// Encoding [`ImageFormat::Auto`] picked for each image, by entry name,
+    /// in the order the entries were packed.
+    pub auto_formats: Vec<(String, AutoChoice)>,
 }
 
 /// Pack files from a directory into an ARC archive (V1 or V2).
 ///
 /// PNG images are encoded with the given [`ImageFormat`]; OGG audio gets a BGI
 /// header; unrecognized files are passed through as-is. Each file's
-/// extension-less name is used as the ARC entry name.
+/// extension-less name is used as the ARC entry name, encoded as CP932.
 ///
 /// Use [`pack_arc_audio`] instead when the directory contains only audio (no
 /// `image_format` parameter needed).
@@ -225,114 +1014,213 @@ pub fn pack_arc(
     version: ArcVersion,
     image_format: ImageFormat,
 ) -> ArcResult<()> {
-    pack_arc_inner(
-        input_dir.as_ref(),
-        output_file.as_ref(),
-        version,
-        image_format,
-    )
+    let options = PackOptions {
+        version: Some(version),
+        encode: EncodeOptions {
+            image_format: Some(image_format),
+            ..EncodeOptions::default()
+        },
+        ..PackOptions::default()
+    };
+    pack_arc_with(input_dir, output_file, &options).map(drop)
 }
 
 /// Pack audio files from a directory into an ARC archive (V1 or V2).
 ///
-/// Convenience wrapper around [`pack_arc`] for directories containing only OGG
-/// audio (and other non-image files). No `image_format` parameter is required.
+/// Convenience wrapper around [`pack_arc_with`] for directories containing
+/// only OGG audio (and other non-image files). No `image_format` parameter is
+/// required.
 pub fn pack_arc_audio(
     input_dir: impl AsRef<Path>,
     output_file: impl AsRef<Path>,
     version: ArcVersion,
 ) -> ArcResult<()> {
-    pack_arc_inner(
-        input_dir.as_ref(),
-        output_file.as_ref(),
-        version,
-        ImageFormat::Bgi,
-    )
+    let options = PackOptions {
+        version: Some(version),
+        ..PackOptions::default()
+    };
+    pack_arc_with(input_dir, output_file, &options).map(drop)
 }
 
-/// Shared implementation for [`pack_arc`] and [`pack_arc_audio`].
-fn pack_arc_inner(
-    input_dir: &Path,
-    output_file: &Path,
-    version: ArcVersion,
-    image_format: ImageFormat,
-) -> ArcResult<()> {
-    info!("Image encoding format: {image_format}");
+/// Pack files from a directory into an ARC archive with explicit
+/// [`PackOptions`].
+///
+/// A directory unpacked by [`unpack_arc_with`] is rebuilt from its
+/// [`manifest::MANIFEST_FILE`] unless [`PackOptions::manifest`] is off: the
+/// archive keeps its entry order and index fields, and its recorded version
+/// unless [`PackOptions::version`] asks for another. Unedited files keep
+/// their original payload (so an untouched directory packs into the original
+/// archive byte-for-byte), edited files are re-encoded with their recorded
+/// format chain, and files not in the manifest are appended using
+/// [`PackOptions::encode`]. An explicit [`EncodeOptions::image_format`]
+/// replaces the recorded chain of every extracted image, edited or not.
+/// Original payloads come from the copied [`manifest::PAYLOAD_FILE`], or
+/// else the source archive, which must be unchanged; without either, as for
+/// a salvaged archive, only the format chains are used.
+pub fn pack_arc_with(
+    input_dir: impl AsRef<Path>,
+    output_file: impl AsRef<Path>,
+    options: &PackOptions,
+) -> ArcResult<PackReport> {
+    match options.encode.image_format {
+        Some(format) => info!("Image encoding format: {format}"),
+        None => info!(
+            "Image encoding format: recorded, or {}",
+            ImageFormat::default()
+        ),
+    }
+    let input_dir = input_dir.as_ref();
+    let manifest = if options.manifest {
+        Manifest::read(input_dir)?
+    } else {
+        None
+    };
 
     // Collect and sort entries for reproducible archive output.
     let mut entries: Vec<_> = fs::read_dir(input_dir)?.collect::<Result<Vec<_>, _>>()?;
     entries.sort_by_key(std::fs::DirEntry::file_name);
+    let files: Vec<_> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if entry.file_name() == MANIFEST_FILE || entry.file_name() == PAYLOAD_FILE {
+                None
+            } else if path.is_file() {
+                Some(path)
+            } else {
+                warn!("{} is not a file, skipping", path.display());
+                None
+            }
+        })
+        .collect();
 
-    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
-
-    for entry in entries {
-        let path = entry.path();
-
-        if !path.is_file() {
-            warn!("{} is not a file, skipping", path.display());
-            continue;
+    let recorded = manifest.as_ref().and_then(|manifest| manifest.version);
+    let version = options.version.or(recorded).unwrap_or_default();
+    if let Some(manifest) = &manifest
+        && let Some(payloads) = manifest.payloads(input_dir)?
+    {
+        info!("Rebuilding the archive recorded in {MANIFEST_FILE}");
+        if let Some(recorded) = recorded
+            && recorded != version
+        {
+            info!("Converting the {recorded:?} archive to {version:?}");
         }
-
-        info!("adding file: {}", path.display());
-
-        // Use filename without extension as the ARC entry name
-        let temp_path = path.with_extension("");
-        let file_name = temp_path
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .ok_or(ArcError::InvalidFormat)?;
-
-        let data = fs::read(&path)?;
-        let encoded = encode_for_pack(&data, image_format)?;
-
-        files.push((file_name, encoded));
+        // The source archive may be the output itself, so it is only
+        // replaced once the rebuild is done reading it.
+        return write_replacing(output_file.as_ref(), |output| {
+            manifest.rebuild(payloads, &files, output, version, options)
+        });
+    }
+    if manifest.is_some() {
+        info!("Re-encoding listed files as recorded in {MANIFEST_FILE}");
     }
 
-    // Write ARC archive
-    let mut arc_file = fs::File::create(output_file)?;
-
-    // Write header: magic (12 bytes) + file count (4 bytes)
-    arc_file.write_all(version.magic())?;
-    arc_file.write_all(&(files.len() as u32).to_le_bytes())?;
-
-    let mut current_offset = 0u32;
-
-    // Write per-file metadata entries.
-    // V1: [16-byte name][4 offset][4 size][8 padding] = 32 bytes
-    // V2: [96-byte name][4 offset][4 size][24 padding] = 128 bytes
-    let name_len_limit = version.name_len();
-    let padding = version.metadata_size() as usize - 8 - name_len_limit;
+    write_replacing(output_file.as_ref(), |output| {
+        let mut writer = create_writer(output, version, options, files.len())?;
+        let mut report = PackReport::default();
+        for path in files {
+            info!("adding file: {}", path.display());
+            let file_name = entry_name(&path)?;
+            let chain = manifest.as_ref().and_then(|manifest| {
+                let entry = manifest.get(&path.file_name()?.to_string_lossy())?;
+                Some(entry.chain.as_slice())
+            });
+            let data = options.encode_entry(&file_name, &fs::read(&path)?, chain, &mut report)?;
+            writer.add_encoded(&file_name, data.as_slice())?;
+        }
 
-    for (file_name, data) in &files {
-        write_filename(&mut arc_file, file_name, name_len_limit)?;
+        writer.finish()?;
+        Ok(report)
+    })
+}
 
-        arc_file.write_all(&current_offset.to_le_bytes())?;
-        arc_file.write_all(&(data.len() as u32).to_le_bytes())?;
+/// The ARC entry name for a file: its name without extension, as used by
+/// [`pack_arc_with`].
+pub fn entry_name(path: &Path) -> ArcResult<String> {
+    path.with_extension("")
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or(ArcError::InvalidFormat)
+}
 
-        // Version-specific trailing padding
-        arc_file.write_all(&vec![0u8; padding])?;
+/// Pack in-memory entries whose names are given as raw index bytes.
+///
+/// Names are written byte-for-byte (no charset conversion), so names that no
+/// charset decodes cleanly survive a round trip. Payloads go through the same
+/// conversion as [`pack_arc_with`]; [`PackOptions::name_encoding`] is only
+/// used to decode names for matching [`PackOptions::bse`] rules.
+pub fn pack_arc_raw<I>(
+    entries: I,
+    output_file: impl AsRef<Path>,
+    options: &PackOptions,
+) -> ArcResult<PackReport>
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    let entries = entries.into_iter();
+    let version = options.version.unwrap_or_default();
+    write_replacing(output_file.as_ref(), |output| {
+        let mut writer = create_writer(output, version, options, entries.size_hint().0)?;
+        let mut report = PackReport::default();
+        for (name, data) in entries {
+            let display_name = options.name_encoding.decode(&name);
+            let data = options.encode_entry(&display_name, &data, None, &mut report)?;
+            writer.add_encoded_raw(&name, data.as_slice())?;
+        }
 
-        current_offset += data.len() as u32;
-    }
+        let mut file = writer.finish()?;
+        // Drop stale bytes left behind if the size hint over-reserved the
+        // index.
+        let len = file.stream_position()?;
+        file.set_len(len)?;
+        Ok(report)
+    })
+}
 
-    // Write raw file data
-    for (_, data) in files {
-        arc_file.write_all(&data)?;
+/// Run `build` on a temporary file next to `output_file` and move it over
+/// `output_file` once `build` succeeds, so a failed pack leaves an existing
+/// archive untouched (even when it is also the one being read).
+pub(crate) fn write_replacing<T>(
+    output_file: &Path,
+    build: impl FnOnce(&Path) -> ArcResult<T>,
+) -> ArcResult<T> {
+    let mut name = output_file
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "output is not a file path"))?
+        .to_os_string();
+    name.push(".tmp");
+    let temp = output_file.with_file_name(name);
+    match build(&temp) {
+        Ok(value) => {
+            fs::rename(&temp, output_file)?;
+            Ok(value)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
     }
-
-    Ok(())
 }
 
-/// Write a null-padded filename into an ARC metadata entry.
-fn write_filename(
-    arc_file: &mut impl Write,
-    file_name: &str,
-    name_len_limit: usize,
-) -> std::io::Result<()> {
-    let mut name_bytes = vec![0u8; name_len_limit];
-    let copy_len = file_name.len().min(name_len_limit);
-    name_bytes[..copy_len].copy_from_slice(&file_name.as_bytes()[..copy_len]);
-    arc_file.write_all(&name_bytes)
+/// Create `output_file` and a `version` [`ArcWriter`] configured from
+/// `options`, with index space reserved for `entries` entries.
+pub(crate) fn create_writer(
+    output_file: &Path,
+    version: ArcVersion,
+    options: &PackOptions,
+    entries: usize,
+) -> ArcResult<ArcWriter<fs::File>> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_file)?;
+    let entries = u32::try_from(entries).map_err(|_| ArcError::ArchiveTooLarge)?;
+    Ok(ArcWriter::with_capacity(file, version, entries)?
+        .with_name_encoding(options.name_encoding)
+        .with_encode_options(options.encode)
+        .with_codecs(options.codecs.clone()))
 }
 
 #[allow(clippy::many_single_char_names)]
@@ -359,7 +1247,13 @@ mod tests {
         let png_data = make_png(rgba, u32::from(width), u32::from(height));
         assert!(is_png(&png_data));
 
-        let encoded = encode_for_pack(&png_data, format).unwrap();
+        let options = EncodeOptions {
+            image_format: Some(format),
+            ..EncodeOptions::default()
+        };
+        let encoded = encode_for_pack("test", &png_data, options, CodecRegistry::builtin())
+            .unwrap()
+            .0;
 
         // decode_file writes to disk; test the decoder directly instead.
         let tmp = tempfile::tempdir().unwrap();
@@ -405,6 +1299,289 @@ mod tests {
             })
             .collect();
         assert_round_trip(&rgba, w, h, ImageFormat::Bgi);
+        assert_round_trip(&rgba, w, h, ImageFormat::BgiScrambled);
+    }
+
+    #[test]
+    fn test_png_grayscale_pipeline() {
+        let (w, h) = (12u16, 9u16);
+        let rgba: Vec<u8> = (0..usize::from(w) * usize::from(h))
+            .flat_map(|i| [i as u8, i as u8, i as u8, 0xFF])
+            .collect();
+        let png = make_png(&rgba, w.into(), h.into());
+        let encode = |image_format, grayscale| {
+            let options = EncodeOptions {
+                image_format: Some(image_format),
+                grayscale,
+                ..EncodeOptions::default()
+            };
+            encode_for_pack("test", &png, options, CodecRegistry::builtin())
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(encode(ImageFormat::Bgi, None)[4], 8);
+        assert_eq!(encode(ImageFormat::Bgi, Some(false))[4], 24);
+        assert_eq!(encode(ImageFormat::CbgV1, None)[0x14], 8);
+        assert_round_trip(&rgba, w, h, ImageFormat::Bgi);
+        assert_round_trip(&rgba, w, h, ImageFormat::CbgV1);
+
+        // Forcing grey stores the luma of colour pixels.
+        let colour = [255u8, 0, 0, 0xFF].repeat(usize::from(w) * usize::from(h));
+        let options = EncodeOptions {
+            grayscale: Some(true),
+            ..EncodeOptions::default()
+        };
+        let encoded = encode_for_pack(
+            "test",
+            &make_png(&colour, w.into(), h.into()),
+            options,
+            CodecRegistry::builtin(),
+        )
+        .unwrap()
+        .0;
+        assert_eq!(encoded[4], 8);
+        assert!(encoded[0x10..].iter().all(|&v| v == 76));
+    }
+
+    #[test]
+    fn test_png_auto_pipeline() {
+        let (w, h) = (64u16, 32u16);
+        let total = usize::from(w) * usize::from(h);
+        let rgba: Vec<u8> = (0..total)
+            .flat_map(|i| [(i / 64 * 8) as u8, 0x40, 0x80, 0xFF])
+            .collect();
+        assert_round_trip(&rgba, w, h, ImageFormat::Auto);
+
+        let png = make_png(&rgba, w.into(), h.into());
+        let encode = |image_format| {
+            let options = EncodeOptions {
+                image_format: Some(image_format),
+                ..EncodeOptions::default()
+            };
+            encode_for_pack("test", &png, options, CodecRegistry::builtin())
+                .unwrap()
+                .0
+        };
+        let auto = encode(ImageFormat::Auto);
+        for format in [ImageFormat::Bgi, ImageFormat::CbgV1, ImageFormat::Dsc] {
+            assert!(auto.len() <= encode(format).len(), "{format}");
+        }
+        assert!(auto.len() < encode(ImageFormat::Bgi).len());
+
+        // Packing reports the pick for each image entry.
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("auto.arc");
+        let options = PackOptions {
+            encode: EncodeOptions {
+                image_format: Some(ImageFormat::Auto),
+                ..EncodeOptions::default()
+            },
+            ..PackOptions::default()
+        };
+        let entries = [
+            (b"text".to_vec(), b"plain text".to_vec()),
+            (b"image".to_vec(), png),
+        ];
+        let report = pack_arc_raw(entries, &arc_path, &options).unwrap();
+        let [(name, choice)] = &report.auto_formats[..] else {
+            panic!("expected one pick, got {:?}", report.auto_formats);
+        };
+        assert_eq!(name, "image");
+        assert_eq!(choice.size, auto.len());
+        let arc = arc::Arc::open(&arc_path).unwrap();
+        assert_eq!(arc.get_file_data(1).unwrap(), auto);
+    }
+
+    /// A `cbg` codec that cannot encode anything.
+    struct FailingCbg;
+
+    impl codec::Codec for FailingCbg {
+        fn name(&self) -> &'static str {
+            "cbg"
+        }
+
+        fn sniff(&self, _: &[u8]) -> bool {
+            false
+        }
+
+        fn decode(&self, _: &[u8]) -> ArcResult<codec::Decoded> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_auto_candidates_use_their_own_format() {
+        let (w, h) = (16u16, 16u16);
+        let mut seed = 0x1234_5678u32;
+        let rgba: Vec<u8> = (0..usize::from(w) * usize::from(h))
+            .flat_map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let [r, g, b, _] = seed.to_le_bytes();
+                [r, g, b, 0xFF]
+            })
+            .collect();
+        let image = DecodedAsset::Image {
+            rgba,
+            width: w,
+            height: h,
+            source_format: SourceFormat::Png,
+        };
+        let codecs = CodecRegistry::builtin().clone().with(i32::MAX, FailingCbg);
+        let options = EncodeOptions {
+            scramble_bgi: true,
+            ..EncodeOptions::default()
+        };
+
+        // Noise is stored smallest as plain BGI: the failed CBG attempt does
+        // not fall back to BGI, and `scramble_bgi` does not scramble the
+        // plain candidate.
+        let (choice, encoded) =
+            encode_image_auto(&image, PixelLayout::Bgr, options, &codecs).unwrap();
+        assert_eq!(choice.format, ImageFormat::Bgi);
+        assert!(!choice.dsc_wrapped);
+        let params = EncodeParams {
+            layout: Some(PixelLayout::Bgr),
+            ..EncodeParams::default()
+        };
+        assert_eq!(encoded, codecs.encode("bgi", &image, params).unwrap());
+    }
+
+    #[test]
+    fn test_png_cbg_v2_pipeline() {
+        let (w, h) = (30u16, 17u16);
+        let rgba = [200u8, 120, 40, 0xFF].repeat(usize::from(w) * usize::from(h));
+        let options = EncodeOptions {
+            image_format: Some(ImageFormat::CbgV2),
+            ..EncodeOptions::default()
+        };
+        let encoded = encode_for_pack(
+            "test",
+            &make_png(&rgba, w.into(), h.into()),
+            options,
+            CodecRegistry::builtin(),
+        )
+        .unwrap()
+        .0;
+        assert!(cbg::is_cbg(&encoded));
+        match decode_bytes(&encoded).unwrap() {
+            DecodedAsset::Image {
+                rgba: decoded,
+                width,
+                height,
+                ..
+            } => {
+                assert_eq!((width, height), (w, h));
+                assert!(decoded.iter().zip(&rgba).all(|(a, b)| a.abs_diff(*b) <= 2));
+            }
+            other => panic!("expected an image, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_png_dsc_pipeline() {
+        let (w, h) = (20u16, 10u16);
+        let total = usize::from(w) * usize::from(h);
+        let rgba: Vec<u8> = (0..total)
+            .flat_map(|i| [(i % 256) as u8, ((i / 4) % 256) as u8, 0x40, 0xFF])
+            .collect();
+        assert_round_trip(&rgba, w, h, ImageFormat::Dsc);
+    }
+
+    #[test]
+    fn test_dsc_compress_nested_payloads() {
+        let options = EncodeOptions {
+            dsc_compress: true,
+            ..EncodeOptions::default()
+        };
+        let script = [SCRIPT_MAGIC, &[1, 2, 3, 4]].concat();
+        let encoded = encode_for_pack("test", &script, options, CodecRegistry::builtin())
+            .unwrap()
+            .0;
+        assert!(dsc::is_dsc(&encoded));
+        assert!(matches!(decode_bytes(&encoded).unwrap(), DecodedAsset::Script(s) if s == script));
+
+        // DSC output that is not a raw image goes back through detection.
+        let ogg = include_bytes!("../test_assets/test.ogg");
+        let wrapped = dsc::encode_dsc(&ogg::add_header(ogg)).unwrap();
+        match decode_bytes(&wrapped).unwrap() {
+            DecodedAsset::Audio { ogg: decoded, .. } => assert_eq!(decoded, ogg),
+            other => panic!("expected audio, got {other:?}"),
+        }
+    }
+
+    /// Stands in for the built-in BGI codec, recording the parameters it is
+    /// asked to encode with.
+    #[derive(Default)]
+    struct ParamsCodec {
+        seen: std::sync::Arc<Mutex<Vec<EncodeParams>>>,
+    }
+
+    impl codec::Codec for ParamsCodec {
+        fn name(&self) -> &'static str {
+            "bgi"
+        }
+
+        fn sniff(&self, _: &[u8]) -> bool {
+            false
+        }
+
+        fn decode(&self, _: &[u8]) -> ArcResult<codec::Decoded> {
+            Err(ArcError::InvalidFormat)
+        }
+
+        fn encode(&self, _: &DecodedAsset, params: EncodeParams) -> ArcResult<Vec<u8>> {
+            self.seen.lock().unwrap().push(params);
+            Ok(b"custom".to_vec())
+        }
+    }
+
+    #[test]
+    fn test_pack_uses_registered_codecs() {
+        let codec = ParamsCodec::default();
+        let seen = codec.seen.clone();
+        let codecs = CodecRegistry::builtin()
+            .clone()
+            .with(codec::priority::BGI + 1, codec);
+        let grey = [0x80u8, 0x80, 0x80, 0xFF].repeat(4);
+        let options = EncodeOptions {
+            image_format: Some(ImageFormat::BgiScrambled),
+            ..EncodeOptions::default()
+        };
+        let mut writer = ArcWriter::new(std::io::Cursor::new(Vec::new()), ArcVersion::V1)
+            .unwrap()
+            .with_encode_options(options)
+            .with_codecs(codecs);
+        writer.add_entry("image", &make_png(&grey, 2, 2)).unwrap();
+        let arc = arc::Arc::from_bytes(writer.finish().unwrap().into_inner()).unwrap();
+        assert_eq!(arc.get_file_data(0).unwrap(), b"custom");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [EncodeParams {
+                layout: Some(PixelLayout::Gray),
+                scramble: true,
+                ..EncodeParams::default()
+            }]
+        );
+
+        // The CBG key reaches the built-in codec.
+        let rgba: Vec<u8> = (0..16u8).flat_map(|i| [i * 9, 255 - i, i, 0xFF]).collect();
+        let options = EncodeOptions {
+            image_format: Some(ImageFormat::CbgV1),
+            cbg_key: Some(0x1234_5678),
+            ..EncodeOptions::default()
+        };
+        let encoded = encode_for_pack(
+            "test",
+            &make_png(&rgba, 4, 4),
+            options,
+            CodecRegistry::builtin(),
+        )
+        .unwrap()
+        .0;
+        let expected =
+            cbg::encode_cbg_v1_with_key(&rgba, 4, 4, PixelLayout::Bgr, 0x1234_5678).unwrap();
+        assert_eq!(encoded, expected);
     }
 
     #[test]
@@ -448,6 +1625,686 @@ mod tests {
         assert_eq!(img.rgba, rgba);
     }
 
+    #[test]
+    fn test_unpack_bounded_in_flight() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path();
+
+        let input_dir = base.join("input");
+        std::fs::create_dir_all(&input_dir).unwrap();
+        for i in 0..16u8 {
+            std::fs::write(input_dir.join(format!("entry{i:02}")), vec![i; 1000]).unwrap();
+        }
+        let arc_path = base.join("test.arc");
+        pack_arc(&input_dir, &arc_path, ArcVersion::V2, ImageFormat::Bgi).unwrap();
+
+        // A cap smaller than any single entry still makes progress, one entry
+        // at a time, and results keep archive order.
+        let output_dir = base.join("output");
+        let options = UnpackOptions {
+            max_in_flight: 1,
+            ..UnpackOptions::default()
+        };
+        let results = unpack_arc_with(&arc_path, &output_dir, &options).unwrap();
+        let names: Vec<_> = results.iter().map(|(n, _)| n.clone()).collect();
+        let expected: Vec<_> = (0..16).map(|i| format!("entry{i:02}")).collect();
+        assert_eq!(names, expected);
+        for (i, (name, r)) in results.iter().enumerate() {
+            r.as_ref().unwrap();
+            assert_eq!(
+                std::fs::read(output_dir.join(name)).unwrap(),
+                vec![i as u8; 1000]
+            );
+        }
+    }
+
+    /// Holds entries starting with `HOLD` for a moment while decoding,
+    /// tracking the most bytes held at once; panics on `BOOM`.
+    #[derive(Default)]
+    struct HoldCodec {
+        held: std::sync::Arc<Mutex<(usize, usize)>>,
+    }
+
+    impl codec::Codec for HoldCodec {
+        fn name(&self) -> &'static str {
+            "hold"
+        }
+
+        fn sniff(&self, data: &[u8]) -> bool {
+            data.starts_with(b"HOLD") || data.starts_with(b"BOOM")
+        }
+
+        fn decode(&self, data: &[u8]) -> ArcResult<codec::Decoded> {
+            assert!(data.starts_with(b"HOLD"), "malformed entry");
+            {
+                let mut held = self.held.lock().unwrap();
+                held.0 += data.len();
+                held.1 = held.1.max(held.0);
+            }
+            thread::sleep(std::time::Duration::from_millis(5));
+            self.held.lock().unwrap().0 -= data.len();
+            Ok(codec::Decoded::Asset(DecodedAsset::Raw(data.to_vec())))
+        }
+    }
+
+    fn pack_entries(path: &Path, entries: impl IntoIterator<Item = Vec<u8>>) {
+        let entries = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| (format!("entry{i:02}").into_bytes(), data));
+        pack_arc_raw(entries, path, &PackOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_in_flight_stays_under_cap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        pack_entries(
+            &arc_path,
+            (0..32).map(|_| [b"HOLD".as_slice(), &[0; 996]].concat()),
+        );
+
+        let codec = HoldCodec::default();
+        let held = codec.held.clone();
+        let options = UnpackOptions {
+            max_in_flight: 3000,
+            codecs: CodecRegistry::new().with(0, codec),
+            ..UnpackOptions::default()
+        };
+        let results = unpack_arc_with(&arc_path, tmp.path().join("out"), &options).unwrap();
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        let peak = held.lock().unwrap().1;
+        assert!((1000..=3000).contains(&peak), "peak {peak}");
+    }
+
+    #[test]
+    fn test_unpack_decoder_panic_does_not_hang() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        pack_entries(
+            &arc_path,
+            (0..16)
+                .map(|i| [if i == 2 { b"BOOM" } else { b"HOLD" }.as_slice(), &[0; 996]].concat()),
+        );
+
+        // The panic must reach the caller instead of leaving the reader
+        // blocked on a budget no worker will ever release.
+        let (tx, rx) = mpsc::channel();
+        let out_dir = tmp.path().join("out");
+        thread::spawn(move || {
+            let options = UnpackOptions {
+                max_in_flight: 1,
+                codecs: CodecRegistry::new().with(0, HoldCodec::default()),
+                ..UnpackOptions::default()
+            };
+            let _ = unpack_arc_with(&arc_path, &out_dir, &options);
+            tx.send(()).unwrap();
+        });
+        match rx.recv_timeout(std::time::Duration::from_secs(30)) {
+            Err(mpsc::RecvTimeoutError::Disconnected) => {}
+            Err(mpsc::RecvTimeoutError::Timeout) => panic!("unpack hung after a decoder panic"),
+            Ok(()) => panic!("the decoder panic was swallowed"),
+        }
+    }
+
+    #[test]
+    fn test_unpack_salvage_bad_payload() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        let text: Vec<u8> = (0..4096u32).map(|i| (i * i % 251) as u8).collect();
+        let mut script = dsc::encode_dsc(&text).unwrap();
+        script.truncate(script.len() / 2);
+        let rgba: Vec<u8> = (0..64u8).flat_map(|v| [v, v / 2, 0x40, 0xFF]).collect();
+        let mut image = cbg::encode_cbg_v2(&rgba, 8, 8, PixelLayout::Bgr, 90).unwrap();
+        image.truncate(0x30 + 0x80 + 2);
+        let entries = [
+            ("text", b"plain text".to_vec()),
+            ("script", script),
+            ("image", image),
+            ("more", vec![7; 100]),
+            ("tail", vec![8; 100]),
+        ];
+        let entries = entries.map(|(name, data)| (name.as_bytes().to_vec(), data));
+        pack_arc_raw(entries, &arc_path, &PackOptions::default()).unwrap();
+
+        // Cut into the last entry so the archive only opens in salvage mode,
+        // which then skips that entry.
+        let data = std::fs::read(&arc_path).unwrap();
+        std::fs::write(&arc_path, &data[..data.len() - 10]).unwrap();
+        let out_dir = tmp.path().join("out");
+        assert!(unpack_arc(&arc_path, &out_dir).is_err());
+
+        let options = UnpackOptions {
+            salvage: true,
+            ..UnpackOptions::default()
+        };
+        let results = unpack_arc_with(&arc_path, &out_dir, &options).unwrap();
+        let names: Vec<_> = results.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["text", "script", "image", "more"]);
+        let failed: Vec<_> = results.iter().map(|(_, r)| r.is_err()).collect();
+        assert_eq!(failed, [false, true, true, false]);
+        assert_eq!(std::fs::read(out_dir.join("text")).unwrap(), b"plain text");
+        assert_eq!(std::fs::read(out_dir.join("more")).unwrap(), [7; 100]);
+    }
+
+    #[test]
+    fn test_pack_unpack_cp932_name() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path();
+
+        let input_dir = base.join("input");
+        std::fs::create_dir_all(&input_dir).unwrap();
+        std::fs::write(input_dir.join("ボイス01.txt"), b"script").unwrap();
+
+        let arc_path = base.join("test.arc");
+        pack_arc(&input_dir, &arc_path, ArcVersion::V1, ImageFormat::Bgi).unwrap();
+
+        // The index must hold CP932 bytes, not UTF-8.
+        let arc = arc::Arc::open(&arc_path).unwrap();
+        assert_eq!(arc.get_file_name(0).unwrap(), "ボイス01");
+        let (expected, _, _) = encoding_rs::SHIFT_JIS.encode("ボイス01");
+        let raw = &std::fs::read(&arc_path).unwrap()[16..16 + expected.len()];
+        assert_eq!(raw, expected.as_ref());
+
+        let output_dir = base.join("output");
+        let results = unpack_arc(&arc_path, &output_dir).unwrap();
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert_eq!(
+            std::fs::read(output_dir.join("ボイス01")).unwrap(),
+            b"script"
+        );
+    }
+
+    #[test]
+    fn test_arc_writer_in_memory() {
+        // Copy an entry out of one archive into a new one built in memory,
+        // without reserving index space up front (forces a data shift).
+        let source = arc::Arc::from_bytes(bytes::Bytes::from_static(include_bytes!(
+            "../test_assets/fixtures/arc_cbg.arc"
+        )))
+        .unwrap();
+        let cbg = source.get_file_bytes(0).unwrap();
+
+        let mut writer = ArcWriter::new(std::io::Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry("script", b"text payload").unwrap();
+        writer.add_encoded("image", &cbg[..]).unwrap();
+        let buf = writer.finish().unwrap().into_inner();
+
+        let arc = arc::Arc::from_bytes(buf).unwrap();
+        assert_eq!(arc.version(), ArcVersion::V1);
+        assert_eq!(arc.files_count(), 2);
+        assert_eq!(arc.get_file_name(0).unwrap(), "script");
+        assert_eq!(&arc.get_file_bytes(0).unwrap()[..], b"text payload");
+        assert_eq!(arc.get_file_name(1).unwrap(), "image");
+        assert_eq!(arc.get_file_bytes(1).unwrap(), cbg);
+
+        // Over-reserving shifts the data region back down on finish.
+        let mut writer =
+            ArcWriter::with_capacity(std::io::Cursor::new(Vec::new()), ArcVersion::V2, 8).unwrap();
+        writer.add_entry("only", b"payload").unwrap();
+        let buf = writer.finish().unwrap().into_inner();
+        let arc = arc::Arc::from_bytes(buf).unwrap();
+        assert_eq!(&arc.get_file_bytes(0).unwrap()[..], b"payload");
+
+        // Raw data and index entries: a gap, a shared payload, index order
+        // apart from data order, and padding.
+        let mut writer = ArcWriter::new(std::io::Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        assert_eq!(writer.add_data(&b"gap"[..]).unwrap(), (0, 3));
+        let (offset, size) = writer.add_data(&b"shared"[..]).unwrap();
+        let entry = |name: &[u8], padding: &[u8]| arc::ArcFile {
+            name: name.to_vec(),
+            offset,
+            size,
+            padding: padding.to_vec(),
+        };
+        writer.add_encoded("last", &b"tail"[..]).unwrap();
+        writer.add_index_entry(entry(b"a", &[7])).unwrap();
+        writer.add_index_entry(entry(b"b", b"")).unwrap();
+        assert!(matches!(
+            writer.add_index_entry(arc::ArcFile {
+                size: 100,
+                ..entry(b"c", b"")
+            }),
+            Err(ArcError::EntryOutOfBounds(3, _, 3, 100))
+        ));
+        let arc = arc::Arc::from_bytes(writer.finish().unwrap().into_inner()).unwrap();
+        assert_eq!(arc.files_count(), 3);
+        assert_eq!(arc.get_file_name(1).unwrap(), "a");
+        assert_eq!(arc.files()[1].padding[0], 7);
+        assert_eq!(&arc.get_file_bytes(1).unwrap()[..], b"shared");
+        assert_eq!(&arc.get_file_bytes(2).unwrap()[..], b"shared");
+        assert_eq!(&arc.get_file_bytes(0).unwrap()[..], b"tail");
+    }
+
+    #[test]
+    fn test_find_entry_by_name() {
+        let mut writer = ArcWriter::new(std::io::Cursor::new(Vec::new()), ArcVersion::V2).unwrap();
+        writer.add_entry("sys_BG01", b"first").unwrap();
+        writer.add_entry("voice", b"second").unwrap();
+        writer.add_entry("sys_bg01", b"third").unwrap();
+        let arc = arc::Arc::from_bytes(writer.finish().unwrap().into_inner()).unwrap();
+
+        assert_eq!(arc.find("voice"), Some(1));
+        assert_eq!(arc.find("voice.ogg"), Some(1));
+        assert_eq!(arc.find("sys_bg01.PNG"), Some(2));
+        assert_eq!(arc.find("voice.txt"), None);
+        assert_eq!(arc.find("VOICE"), None);
+        assert!(matches!(
+            arc.entry("missing"),
+            Err(error::ArcError::EntryNotFound(_))
+        ));
+
+        // Exact matches still win over case-folded ones.
+        let arc = arc.with_case_insensitive_lookup(true);
+        assert_eq!(arc.find("VOICE.ogg"), Some(1));
+        assert_eq!(arc.find("SYS_BG01"), Some(0));
+        assert_eq!(arc.entry("sys_bg01").unwrap(), b"third");
+    }
+
+    #[test]
+    fn test_open_rejects_corrupt_header() {
+        let mut writer = ArcWriter::new(std::io::Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry("first", &[1u8; 10]).unwrap();
+        writer.add_entry("second", &[2u8; 10]).unwrap();
+        let buf = writer.finish().unwrap().into_inner();
+
+        // A huge entry count is rejected before the index is allocated.
+        let mut data = buf.clone();
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            arc::Arc::from_bytes(data),
+            Err(error::ArcError::TooManyEntries(u32::MAX, 2))
+        ));
+
+        // An entry running past the end of the file is reported by name.
+        let mut data = buf;
+        let size_field = 16 + 32 + 20;
+        data[size_field..size_field + 4].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        match arc::Arc::from_bytes(data.clone()) {
+            Err(error::ArcError::EntryOutOfBounds(1, name, _, 0x7fff_ffff)) => {
+                assert_eq!(name, "second");
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        // Unchecked opening defers the error to reading that entry.
+        let arc = arc::Arc::from_reader_unchecked(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(arc.get_file_data(0).unwrap(), [1u8; 10]);
+        assert!(matches!(
+            arc.get_file_data(1),
+            Err(error::ArcError::EntryOutOfBounds(1, ..))
+        ));
+    }
+
+    #[test]
+    fn test_pack_raw_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+
+        // "中文" in GBK, followed by a name no charset decodes cleanly.
+        let gbk_name = vec![0xD6, 0xD0, 0xCE, 0xC4];
+        let junk_name = vec![0x81, 0xFF, b'a'];
+        let options = PackOptions::default();
+        pack_arc_raw(
+            [
+                (gbk_name.clone(), b"one".to_vec()),
+                (junk_name.clone(), b"two".to_vec()),
+            ],
+            &arc_path,
+            &options,
+        )
+        .unwrap();
+
+        let arc = arc::Arc::open(&arc_path)
+            .unwrap()
+            .with_name_encoding(NameEncoding::Gbk);
+        assert_eq!(arc.get_raw_name(0).unwrap(), gbk_name);
+        assert_eq!(arc.get_file_name(0).unwrap(), "中文");
+        assert_eq!(arc.get_raw_name(1).unwrap(), junk_name);
+        assert!(arc.get_file_name(1).unwrap().contains('\u{FFFD}'));
+    }
+
+    #[test]
+    fn test_failed_pack_keeps_old_archive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+        let options = PackOptions::default();
+        pack_arc_raw([(b"one".to_vec(), b"data".to_vec())], &arc_path, &options).unwrap();
+        let before = fs::read(&arc_path).unwrap();
+
+        // A broken PNG fails after the first entry is already written.
+        let broken_png = [b"\x89PNG\r\n\x1a\n".as_slice(), &[0; 16]].concat();
+        let entries = [
+            (b"one".to_vec(), b"new data".to_vec()),
+            (b"two".to_vec(), broken_png.clone()),
+        ];
+        assert!(pack_arc_raw(entries, &arc_path, &options).is_err());
+        assert_eq!(fs::read(&arc_path).unwrap(), before);
+
+        let dir = tmp.path().join("in");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("one.txt"), b"new data").unwrap();
+        fs::write(dir.join("two.png"), broken_png).unwrap();
+        assert!(pack_arc_with(&dir, &arc_path, &options).is_err());
+        assert_eq!(fs::read(&arc_path).unwrap(), before);
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_pack_bse_rules() {
+        let tmp = tempfile::tempdir().unwrap();
+        let arc_path = tmp.path().join("test.arc");
+
+        let script = [SCRIPT_MAGIC, &[0x55; 0x40]].concat();
+        let options = PackOptions {
+            bse: vec![bse::BseRule::new("scr*", bse::BseVersion::V101)],
+            ..PackOptions::default()
+        };
+        pack_arc_raw(
+            [
+                (b"script".to_vec(), script.clone()),
+                (b"text".to_vec(), script.clone()),
+                (b"scr_short".to_vec(), b"short".to_vec()),
+            ],
+            &arc_path,
+            &options,
+        )
+        .unwrap();
+
+        let arc = arc::Arc::open(&arc_path).unwrap();
+        let wrapped = arc.get_file_data(0).unwrap();
+        assert!(bse::is_bse(&wrapped));
+        assert_eq!(u16::from_le_bytes([wrapped[8], wrapped[9]]), 0x101);
+        assert!(matches!(decode_bytes(&wrapped).unwrap(), DecodedAsset::Script(s) if s == script));
+        assert_eq!(arc.get_file_data(1).unwrap(), script);
+        assert_eq!(arc.get_file_data(2).unwrap(), b"short");
+    }
+
+    #[test]
+    fn test_repack_keeps_format_chain() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (w, h) = (16u16, 8u16);
+        let gray: Vec<u8> = (0..128u8).flat_map(|v| [v, v, v, 0xFF]).collect();
+        let colour: Vec<u8> = (0..128u8).flat_map(|v| [v, 0x20, !v, 0xFF]).collect();
+        let cg =
+            dsc::encode_dsc(&bgi::encode_bgi_scrambled(&gray, w, h, PixelLayout::Gray)).unwrap();
+        let entries = [
+            (
+                b"cg".to_vec(),
+                bse::encrypt_bse(&cg, bse::BseVersion::V101, 1).unwrap(),
+            ),
+            (
+                b"bg".to_vec(),
+                cbg::encode_cbg_v1(&colour, w, h, PixelLayout::Bgr).unwrap(),
+            ),
+            (
+                b"se".to_vec(),
+                ogg::add_header(include_bytes!("../test_assets/test.ogg")),
+            ),
+            (b"scr".to_vec(), [SCRIPT_MAGIC, &[0x55; 0x40]].concat()),
+        ];
+        let arc_path = tmp.path().join("test.arc");
+        pack_arc_raw(entries.clone(), &arc_path, &PackOptions::default()).unwrap();
+
+        let out_dir = tmp.path().join("out");
+        unpack_arc(&arc_path, &out_dir).unwrap();
+        let manifest = Manifest::read(&out_dir).unwrap().unwrap();
+        let chains: Vec<_> = manifest
+            .entries
+            .iter()
+            .map(|e| (e.file.as_str(), e.chain.clone()))
+            .collect();
+        assert_eq!(
+            chains,
+            [
+                (
+                    "cg.png",
+                    vec![
+                        Layer::Bse(bse::BseVersion::V101),
+                        Layer::Dsc,
+                        Layer::Image(ImageFormat::BgiScrambled, PixelLayout::Gray),
+                    ]
+                ),
+                (
+                    "bg.png",
+                    vec![Layer::Image(ImageFormat::CbgV1, PixelLayout::Bgr)]
+                ),
+                ("se.ogg", vec![Layer::Audio]),
+                ("scr", Vec::new()),
+            ]
+        );
+
+        // Without an explicit image format an edited image keeps its chain.
+        let edited: Vec<u8> = (0..128u8)
+            .flat_map(|v| [v / 2, v / 2, v / 2, 0xFF])
+            .collect();
+        std::fs::write(
+            out_dir.join("cg.png"),
+            make_png(&edited, w.into(), h.into()),
+        )
+        .unwrap();
+        let repacked = tmp.path().join("repacked.arc");
+        pack_arc_with(&out_dir, &repacked, &PackOptions::default()).unwrap();
+
+        let arc = arc::Arc::open(&repacked).unwrap();
+        assert_eq!(arc.files_count(), 4);
+        for i in 0..arc.files_count() {
+            let name = arc.get_file_name(i).unwrap().to_string();
+            let file = manifest
+                .entries
+                .iter()
+                .find(|e| e.file.starts_with(&name))
+                .unwrap();
+            let (asset, chain) = CodecRegistry::builtin()
+                .decode_layers(&arc.get_file_data(i).unwrap())
+                .unwrap();
+            assert_eq!(chain, file.chain, "{name}");
+            if name == "cg" {
+                assert!(matches!(asset, DecodedAsset::Image { rgba, .. } if rgba == edited));
+            }
+        }
+
+        // An explicit format re-encodes every image, edited or not.
+        pack_arc(&out_dir, &repacked, ArcVersion::V2, ImageFormat::CbgV1).unwrap();
+        let arc = arc::Arc::open(&repacked).unwrap();
+        for i in 0..2 {
+            let (_, chain) = CodecRegistry::builtin()
+                .decode_layers(&arc.get_file_data(i).unwrap())
+                .unwrap();
+            let [Layer::Image(format, _)] = chain[..] else {
+                panic!("expected a bare image, got {chain:?}");
+            };
+            assert_eq!(format, ImageFormat::CbgV1);
+        }
+    }
+
+    #[test]
+    fn test_repack_reproduces_archive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let rgba: Vec<u8> = (0..64u8).flat_map(|v| [v, 0x40, !v, 0xFF]).collect();
+        let img = bgi::encode_bgi(&rgba, 8, 8, PixelLayout::Bgr);
+        let script = [SCRIPT_MAGIC, b"hello".as_slice()].concat();
+        let broken = [b"DSC FORMAT 1.00".as_slice(), &[0; 17]].concat();
+
+        // Index order differs from data order; payloads are separated by
+        // stray bytes, one is shared, one cannot be decoded, and the index
+        // carries junk after a name's null and in the padding.
+        let mut data = b"LEAD".to_vec();
+        let mut place = |payload: &[u8], gap: &[u8]| {
+            let offset = data.len() as u32;
+            data.extend_from_slice(payload);
+            data.extend_from_slice(gap);
+            (offset, payload.len() as u32)
+        };
+        let img_at = place(&img, &[0; 3]);
+        let script_at = place(&script, b"");
+        let broken_at = place(&broken, b"TAIL");
+        let file = |name: &[u8], (offset, size): (u32, u32), padding: &[u8]| {
+            let mut field = name.to_vec();
+            field.resize(ArcVersion::V1.name_len(), 0);
+            arc::ArcFile {
+                name: field,
+                offset,
+                size,
+                padding: padding.to_vec(),
+            }
+        };
+        let files = [
+            file(b"scr", script_at, b""),
+            file(b"img\0junk", img_at, &[1, 2, 3]),
+            file(b"broken", broken_at, b""),
+            file(b"alias", script_at, b""),
+            file(b"empty", (script_at.0, 0), b""),
+        ];
+        let mut original = Vec::new();
+        arc::write_index(&mut original, ArcVersion::V1, &files).unwrap();
+        original.extend_from_slice(&data);
+        let arc_path = tmp.path().join("test.arc");
+        std::fs::write(&arc_path, &original).unwrap();
+
+        let out_dir = tmp.path().join("out");
+        let results = unpack_arc(&arc_path, &out_dir).unwrap();
+        assert_eq!(results.iter().filter(|(_, r)| r.is_err()).count(), 1);
+        let repacked = tmp.path().join("repacked.arc");
+        pack_arc_with(&out_dir, &repacked, &PackOptions::default()).unwrap();
+        assert_eq!(std::fs::read(&repacked).unwrap(), original);
+
+        // An explicit version converts the index and keeps the data region.
+        let options = PackOptions {
+            version: Some(ArcVersion::V2),
+            ..PackOptions::default()
+        };
+        pack_arc_with(&out_dir, &repacked, &options).unwrap();
+        let converted = std::fs::read(&repacked).unwrap();
+        assert_eq!(&converted[..12], ArcVersion::V2.magic());
+        let data_start = arc::index_len(ArcVersion::V2, 5) as usize;
+        assert_eq!(converted[data_start..], data);
+        let arc = arc::Arc::open(&repacked).unwrap();
+        assert_eq!(arc.files()[1].name[..8], *b"img\0junk");
+        assert_eq!(arc.get_file_data(1).unwrap(), img);
+
+        for fixture in ["arc_audio.arc", "arc_bgi.arc", "arc_cbg.arc"] {
+            let fixture = Path::new("test_assets/fixtures").join(fixture);
+            let out_dir = tmp.path().join(fixture.file_stem().unwrap());
+            unpack_arc(&fixture, &out_dir).unwrap();
+            pack_arc_with(&out_dir, &repacked, &PackOptions::default()).unwrap();
+            assert_eq!(
+                std::fs::read(&repacked).unwrap(),
+                std::fs::read(&fixture).unwrap(),
+                "{}",
+                fixture.display()
+            );
+        }
+
+        // Only the edited file is re-encoded; a deleted file drops its entry
+        // and a new file is appended.
+        std::fs::write(
+            out_dir.join("scr"),
+            [SCRIPT_MAGIC, b"edited".as_slice()].concat(),
+        )
+        .unwrap();
+        std::fs::remove_file(out_dir.join("empty")).unwrap();
+        std::fs::write(out_dir.join("new.txt"), b"new").unwrap();
+        pack_arc_with(&out_dir, &repacked, &PackOptions::default()).unwrap();
+
+        let arc = arc::Arc::open(&repacked).unwrap();
+        assert_eq!(arc.version(), ArcVersion::V1);
+        let names: Vec<_> = (0..arc.files_count())
+            .map(|i| arc.get_file_name(i).unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["scr", "img", "broken", "alias", "new"]);
+        assert_eq!(arc.files()[1].name[..8], *b"img\0junk");
+        assert_eq!(arc.files()[1].padding[..3], [1, 2, 3]);
+        assert_eq!(
+            arc.get_file_data(0).unwrap(),
+            [SCRIPT_MAGIC, b"edited".as_slice()].concat()
+        );
+        assert_eq!(arc.get_file_data(1).unwrap(), img);
+        assert_eq!(arc.get_file_data(2).unwrap(), broken);
+        assert_eq!(arc.get_file_data(3).unwrap(), script);
+        assert_eq!(arc.get_file_data(4).unwrap(), b"new");
+    }
+
+    #[test]
+    fn test_repack_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let original = include_bytes!("../test_assets/fixtures/arc_bgi.arc");
+        let arc_path = tmp.path().join("data.arc");
+        std::fs::write(&arc_path, original).unwrap();
+        let out_dir = tmp.path().join("out");
+        unpack_arc(&arc_path, &out_dir).unwrap();
+
+        // Untouched payloads are read from the archive being replaced.
+        pack_arc_with(&out_dir, &arc_path, &PackOptions::default()).unwrap();
+        assert_eq!(std::fs::read(&arc_path).unwrap(), original);
+
+        let manifest = Manifest::read(&out_dir).unwrap().unwrap();
+        let png = out_dir.join(&manifest.entries[0].file);
+        let image = write::read_png(&std::fs::read(&png).unwrap()).unwrap();
+        let edited: Vec<u8> = image.rgba.iter().map(|&v| v | 1).collect();
+        std::fs::write(
+            &png,
+            make_png(&edited, image.width.into(), image.height.into()),
+        )
+        .unwrap();
+        for _ in 0..2 {
+            pack_arc_with(&out_dir, &arc_path, &PackOptions::default()).unwrap();
+            let arc = arc::Arc::open(&arc_path).unwrap();
+            assert!(matches!(
+                decode_bytes(&arc.get_file_data(0).unwrap()).unwrap(),
+                DecodedAsset::Image { rgba, .. } if rgba == edited
+            ));
+        }
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_repack_payload_sources() {
+        let tmp = tempfile::tempdir().unwrap();
+        let original = include_bytes!("../test_assets/fixtures/arc_cbg.arc");
+        let arc_path = tmp.path().join("source.arc");
+        std::fs::write(&arc_path, original).unwrap();
+        let repacked = tmp.path().join("repacked.arc");
+
+        // By default the data region is copied next to the manifest.
+        let copied = tmp.path().join("copied");
+        unpack_arc(&arc_path, &copied).unwrap();
+        assert!(copied.join(PAYLOAD_FILE).exists());
+
+        // Without the copy, untouched payloads are read back from the source
+        // archive.
+        let out_dir = tmp.path().join("out");
+        let options = UnpackOptions {
+            copy_payloads: false,
+            ..UnpackOptions::default()
+        };
+        unpack_arc_with(&arc_path, &out_dir, &options).unwrap();
+        assert!(!out_dir.join(PAYLOAD_FILE).exists());
+        pack_arc_with(&out_dir, &repacked, &PackOptions::default()).unwrap();
+        assert_eq!(std::fs::read(&repacked).unwrap(), original);
+
+        // Once the source changes, the rebuild fails rather than quietly
+        // re-encoding every file.
+        let mut changed = original.to_vec();
+        *changed.last_mut().unwrap() ^= 1;
+        std::fs::write(&arc_path, &changed).unwrap();
+        assert!(matches!(
+            pack_arc_with(&out_dir, &repacked, &PackOptions::default()),
+            Err(ArcError::InvalidManifest(e)) if e.contains("has changed")
+        ));
+        std::fs::remove_file(&arc_path).unwrap();
+        assert!(matches!(
+            pack_arc_with(&out_dir, &repacked, &PackOptions::default()),
+            Err(ArcError::InvalidManifest(e)) if e.contains("is gone")
+        ));
+        assert_eq!(std::fs::read(&repacked).unwrap(), original);
+
+        // A payload copy does not depend on the source.
+        std::fs::remove_file(&repacked).unwrap();
+        pack_arc_with(&copied, &repacked, &PackOptions::default()).unwrap();
+        assert_eq!(std::fs::read(&repacked).unwrap(), original);
+    }
+
     // -----------------------------------------------------------------------
     // Decode-from-fixture tests
     // -----------------------------------------------------------------------
@@ -541,4 +2398,157 @@ mod tests {
         let expected = include_bytes!("../test_assets/test.ogg");
         assert_eq!(ogg_data, expected.as_slice());
     }
+
+    // -----------------------------------------------------------------------
+    // Archive access and in-memory decode tests
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn test_arc_from_nested_reader() {
+        let arc_data = include_bytes!("../test_assets/fixtures/arc_bgi.arc");
+
+        // Embed the archive after an unrelated prefix, as a container would.
+        let mut container = b"container header".to_vec();
+        container.extend_from_slice(arc_data);
+        let mut cursor = std::io::Cursor::new(container);
+        cursor.set_position(16);
+
+        let arc = arc::Arc::from_reader(cursor).unwrap();
+        assert_eq!(arc.files_count(), 1);
+        assert_eq!(arc.get_file_name(0).unwrap(), "image");
+        let data = arc.get_file_data(0).unwrap();
+        let (pixels, w, h) = bgi::decrypt_bgi(&data).unwrap();
+        let (expected, ew, eh) = expected_bgi_pixels();
+        assert_eq!((w, h), (ew, eh));
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn test_entries_sniff_kind() {
+        for (fixture, kind) in [
+            ("arc_bgi.arc", EntryKind::Bgi),
+            ("arc_cbg.arc", EntryKind::Cbg),
+            ("arc_audio.arc", EntryKind::Audio),
+        ] {
+            let arc = arc::Arc::open(format!("test_assets/fixtures/{fixture}")).unwrap();
+            let entries: Vec<_> = arc.entries().collect();
+            assert_eq!(entries.len(), arc.files_count() as usize);
+            for (i, info) in (&arc).into_iter().enumerate() {
+                assert_eq!(info, entries[i]);
+                assert_eq!(info.index, i as u32);
+                assert_eq!(info.name, arc.get_file_name(info.index).unwrap());
+                assert_eq!(info.size, arc.get_file_size(info.index).unwrap());
+                assert_eq!(info.kind, kind, "{fixture}: {}", info.name);
+                assert!(!info.bse);
+            }
+        }
+
+        let mut writer = ArcWriter::new(std::io::Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry("script", b"text payload").unwrap();
+        let arc = arc::Arc::from_bytes(writer.finish().unwrap().into_inner()).unwrap();
+        let raw: Vec<_> = arc.entries().filter(|e| e.kind == EntryKind::Raw).collect();
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].raw_name, b"script");
+        assert_eq!(raw[0].offset, 16 + 32);
+    }
+
+    #[test]
+    fn test_open_entry_stream() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let arc = arc::Arc::open("test_assets/fixtures/arc_audio.arc").unwrap();
+        let last = arc.files_count() - 1;
+        let expected = arc.get_file_data(last).unwrap();
+
+        let mut reader = arc.open_entry(last).unwrap();
+        assert_eq!(reader.len(), expected.len() as u64);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, expected);
+        assert_eq!(reader.read(&mut [0u8; 4]).unwrap(), 0);
+
+        let mut tail = Vec::new();
+        assert_eq!(
+            reader.seek(SeekFrom::End(-10)).unwrap(),
+            expected.len() as u64 - 10
+        );
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, expected[expected.len() - 10..]);
+
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        reader.seek(SeekFrom::Current(-2)).unwrap();
+        let mut head = [0u8; 6];
+        reader.read_exact(&mut head).unwrap();
+        assert_eq!(head, expected[2..8]);
+        assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+
+        // The OGG stream inside the BGI wrapper decodes straight off the entry.
+        let mut reader = arc.open_entry(last).unwrap();
+        reader.seek(SeekFrom::Start(0x40)).unwrap();
+        let ogg = lewton::inside_ogg::OggStreamReader::new(reader).unwrap();
+        assert!(ogg.ident_hdr.audio_sample_rate > 0);
+    }
+
+    #[test]
+    fn test_decode_bytes() {
+        for (fixture, format) in [
+            ("arc_bgi.arc", SourceFormat::Bgi),
+            ("arc_cbg.arc", SourceFormat::Cbg),
+        ] {
+            let arc = arc::Arc::open(format!("test_assets/fixtures/{fixture}")).unwrap();
+            match decode_bytes(&arc.get_file_data(0).unwrap()).unwrap() {
+                DecodedAsset::Image {
+                    rgba,
+                    width,
+                    height,
+                    source_format,
+                } => {
+                    assert_eq!(source_format, format);
+                    assert_eq!(rgba.len(), usize::from(width) * usize::from(height) * 4);
+                }
+                other => panic!("{fixture}: expected an image, got {other:?}"),
+            }
+        }
+
+        let ogg = include_bytes!("../test_assets/test.ogg");
+        let wrapped = ogg::add_header(ogg);
+        let asset = decode_bytes(&wrapped).unwrap();
+        assert_eq!(asset.extension(), Some("ogg"));
+        assert_eq!(
+            asset,
+            DecodedAsset::Audio {
+                ogg: ogg.to_vec(),
+                header: wrapped[..0x40].to_vec(),
+            }
+        );
+
+        let script = [SCRIPT_MAGIC, b"code"].concat();
+        assert_eq!(
+            decode_bytes(&script).unwrap(),
+            DecodedAsset::Script(script.clone())
+        );
+        assert_eq!(
+            decode_bytes(b"text").unwrap(),
+            DecodedAsset::Raw(b"text".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_arc_zero_copy_shared_reads() {
+        let arc_data =
+            bytes::Bytes::from_static(include_bytes!("../test_assets/fixtures/arc_cbg.arc"));
+        let arc = arc::Arc::from_bytes(arc_data.clone()).unwrap();
+
+        // The entry handle must point into the archive buffer, not a copy.
+        let entry = arc.get_file_bytes(0).unwrap();
+        let buf = arc_data.as_ptr_range();
+        assert!(buf.contains(&entry.as_ptr()));
+
+        // Shared `&Arc` reads from several rayon workers agree with each other.
+        let copies: Vec<Vec<u8>> = (0..8)
+            .into_par_iter()
+            .map(|_| arc.get_file_data(0).unwrap())
+            .collect();
+        assert!(copies.iter().all(|c| c[..] == entry[..]));
+    }
 }
@@ -1,8 +1,15 @@
 use std::path::PathBuf;
 
-use arc_reader::{ImageFormat, arc::ArcVersion};
+use arc_reader::{
+    EncodeOptions, ImageFormat, PackOptions, UnpackOptions,
+    arc::{Arc, ArcVersion, NameEncoding},
+    bse::{BseRule, BseVersion},
+    cbg::DEFAULT_QUALITY,
+    edit::ArcEditor,
+    entry_name,
+};
 use clap::{Parser, Subcommand};
-use log::{error, info};
+use log::{error, info, warn};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -22,6 +29,28 @@ enum Commands {
         /// Output directory path (optional)
         #[arg(required = false)]
         output_path: Option<PathBuf>,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
+
+        /// Maximum MiB of entry data held in memory while decoding
+        #[arg(long, default_value_t = arc_reader::DEFAULT_MAX_IN_FLIGHT >> 20)]
+        max_in_flight: usize,
+
+        /// Recover what is left of a truncated or corrupted archive
+        #[arg(long)]
+        salvage: bool,
+
+        /// Do not write the manifest that lets `pack` rebuild the archive
+        #[arg(long)]
+        no_manifest: bool,
+
+        /// Do not copy the archive's data region next to the manifest; `pack`
+        /// then reads it from the archive and fails once that is moved or
+        /// modified
+        #[arg(long)]
+        no_copy_payloads: bool,
     },
     /// Pack directory into ARC file
     Pack {
@@ -33,14 +62,157 @@ enum Commands {
         #[arg(required = false)]
         output_file: Option<PathBuf>,
 
-        /// ARC version
-        #[arg(long, short, default_value = "2", value_parser = parse_version)]
-        version: ArcVersion,
+        /// ARC version [default: 2, or the one recorded in the input
+        /// directory's manifest]
+        #[arg(long, short, value_parser = parse_version)]
+        version: Option<ArcVersion>,
+
+        /// Image encoding format: `"bgi"`, `"bgi-scrambled"` (snake-delta
+        /// BGI), `"cbg"` (`CompressedBG` V1), `"cbg2"` (lossy `CompressedBG`
+        /// V2), `"dsc"` (DSC-compressed raw image) or `"auto"` (smallest
+        /// lossless format per image) [default: each image's format recorded
+        /// in the input directory's manifest, else bgi]
+        #[arg(long, short, value_parser = |s: &str| ImageFormat::try_from(s))]
+        image: Option<ImageFormat>,
+
+        /// Quality (1-100) of lossy image formats
+        #[arg(long, short, default_value_t = DEFAULT_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Store images as 8 bpp grey: `true` always, `false` never; by
+        /// default only images whose pixels are all opaque grey
+        #[arg(long, value_name = "BOOL")]
+        grayscale: Option<bool>,
+
+        /// DSC-compress entries that are neither PNG images nor OGG audio
+        #[arg(long)]
+        dsc: bool,
+
+        /// Wrap entries whose name matches this pattern (`*` and `?`
+        /// wildcards) in BSE; may be given multiple times
+        #[arg(long, value_name = "PATTERN")]
+        bse: Vec<String>,
+
+        /// BSE version used by `--bse`: `"1.0"` (default) or `"1.1"`
+        #[arg(long, default_value = "1.0", value_parser = |s: &str| BseVersion::try_from(s))]
+        bse_version: BseVersion,
+
+        /// Ignore the input directory's unpack manifest and encode every file
+        /// with the options above (by default an unpacked archive is rebuilt
+        /// with its original version, order and unedited payloads)
+        #[arg(long)]
+        no_manifest: bool,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
+    },
+    /// Replace entries of an ARC file in place
+    Replace {
+        /// Path to ARC file
+        #[arg(required = true)]
+        arc_file: PathBuf,
+
+        /// Replacement files; each replaces the entry named after its
+        /// extension-less file name
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Image encoding format: `"bgi"` (default), `"bgi-scrambled"`
+        /// (snake-delta BGI), `"cbg"` (`CompressedBG` V1), `"cbg2"` (lossy
+        /// `CompressedBG` V2), `"dsc"` (DSC-compressed raw image) or `"auto"`
+        /// (smallest lossless format per image)
+        #[arg(long, short, default_value = "bgi", value_parser = |s: &str| ImageFormat::try_from(s))]
+        image: ImageFormat,
+
+        /// Quality (1-100) of lossy image formats
+        #[arg(long, short, default_value_t = DEFAULT_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Store images as 8 bpp grey: `true` always, `false` never; by
+        /// default only images whose pixels are all opaque grey
+        #[arg(long, value_name = "BOOL")]
+        grayscale: Option<bool>,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
+    },
+    /// Append new entries to an ARC file
+    Add {
+        /// Path to ARC file
+        #[arg(required = true)]
+        arc_file: PathBuf,
+
+        /// Files to add, named after their extension-less file name
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
 
-        /// Image encoding format: `"bgi"` (default) or `"cbg"` (`CompressedBG`
-        /// V1)
+        /// Image encoding format: `"bgi"` (default), `"bgi-scrambled"`
+        /// (snake-delta BGI), `"cbg"` (`CompressedBG` V1), `"cbg2"` (lossy
+        /// `CompressedBG` V2), `"dsc"` (DSC-compressed raw image) or `"auto"`
+        /// (smallest lossless format per image)
         #[arg(long, short, default_value = "bgi", value_parser = |s: &str| ImageFormat::try_from(s))]
         image: ImageFormat,
+
+        /// Quality (1-100) of lossy image formats
+        #[arg(long, short, default_value_t = DEFAULT_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: u8,
+
+        /// Store images as 8 bpp grey: `true` always, `false` never; by
+        /// default only images whose pixels are all opaque grey
+        #[arg(long, value_name = "BOOL")]
+        grayscale: Option<bool>,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
+    },
+    /// Remove entries from an ARC file
+    Remove {
+        /// Path to ARC file
+        #[arg(required = true)]
+        arc_file: PathBuf,
+
+        /// Names of the entries to remove
+        #[arg(required = true)]
+        names: Vec<String>,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
+    },
+    /// Rename an entry of an ARC file
+    Rename {
+        /// Path to ARC file
+        #[arg(required = true)]
+        arc_file: PathBuf,
+
+        /// Current entry name
+        #[arg(required = true)]
+        old_name: String,
+
+        /// New entry name
+        #[arg(required = true)]
+        new_name: String,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
+    },
+    /// Check an ARC file for structural problems
+    Verify {
+        /// Path to ARC file
+        #[arg(required = true)]
+        arc_file: PathBuf,
+
+        /// Also try to decode every entry
+        #[arg(long, short)]
+        decode: bool,
+
+        /// Charset of entry names: `"cp932"` (default), `"gbk"` or `"utf8"`
+        #[arg(long, short, default_value = "cp932", value_parser = |s: &str| NameEncoding::try_from(s))]
+        encoding: NameEncoding,
     },
 }
 
@@ -57,9 +229,22 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
         Commands::Unpack {
             arc_file,
             output_path,
+            encoding,
+            max_in_flight,
+            salvage,
+            no_manifest,
+            no_copy_payloads,
         } => {
             let out_dir = output_path.unwrap_or(arc_file.with_extension(""));
-            let results = arc_reader::unpack_arc(&arc_file, &out_dir)?;
+            let options = UnpackOptions {
+                name_encoding: encoding,
+                max_in_flight: max_in_flight.saturating_mul(1 << 20),
+                salvage,
+                manifest: !no_manifest,
+                copy_payloads: !no_copy_payloads,
+                ..UnpackOptions::default()
+            };
+            let results = arc_reader::unpack_arc_with(&arc_file, &out_dir, &options)?;
 
             let errors: Vec<_> = results
                 .into_iter()
@@ -78,11 +263,126 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
             output_file,
             version,
             image,
+            quality,
+            grayscale,
+            dsc,
+            bse,
+            bse_version,
+            no_manifest,
+            encoding,
         } => {
             let output = output_file.unwrap_or(input_dir.with_extension("arc"));
-            arc_reader::pack_arc(&input_dir, &output, version, image)?;
+            let options = PackOptions {
+                version,
+                encode: EncodeOptions {
+                    image_format: image,
+                    quality,
+                    dsc_compress: dsc,
+                    grayscale,
+                    ..EncodeOptions::default()
+                },
+                name_encoding: encoding,
+                bse: bse
+                    .into_iter()
+                    .map(|pattern| BseRule::new(pattern, bse_version))
+                    .collect(),
+                manifest: !no_manifest,
+                ..PackOptions::default()
+            };
+            arc_reader::pack_arc_with(&input_dir, &output, &options)?;
             info!("Packed to {}", output.display());
         }
+        Commands::Replace {
+            arc_file,
+            files,
+            image,
+            quality,
+            grayscale,
+            encoding,
+        } => {
+            let mut editor = ArcEditor::open(&arc_file)?
+                .with_encode_options(EncodeOptions {
+                    image_format: Some(image),
+                    quality,
+                    grayscale,
+                    ..EncodeOptions::default()
+                })
+                .with_name_encoding(encoding);
+            for path in &files {
+                let name = entry_name(path)?;
+                info!("replacing {name} with {}", path.display());
+                editor.replace(&name, &std::fs::read(path)?)?;
+            }
+            editor.commit()?;
+        }
+        Commands::Add {
+            arc_file,
+            files,
+            image,
+            quality,
+            grayscale,
+            encoding,
+        } => {
+            let mut editor = ArcEditor::open(&arc_file)?
+                .with_encode_options(EncodeOptions {
+                    image_format: Some(image),
+                    quality,
+                    grayscale,
+                    ..EncodeOptions::default()
+                })
+                .with_name_encoding(encoding);
+            for path in &files {
+                let name = entry_name(path)?;
+                info!("adding {name} from {}", path.display());
+                editor.add(&name, &std::fs::read(path)?)?;
+            }
+            editor.commit()?;
+        }
+        Commands::Remove {
+            arc_file,
+            names,
+            encoding,
+        } => {
+            let mut editor = ArcEditor::open(&arc_file)?.with_name_encoding(encoding);
+            for name in &names {
+                info!("removing {name}");
+                editor.remove(name)?;
+            }
+            editor.commit()?;
+        }
+        Commands::Rename {
+            arc_file,
+            old_name,
+            new_name,
+            encoding,
+        } => {
+            let mut editor = ArcEditor::open(&arc_file)?.with_name_encoding(encoding);
+            info!("renaming {old_name} to {new_name}");
+            editor.rename(&old_name, &new_name)?;
+            editor.commit()?;
+        }
+        Commands::Verify {
+            arc_file,
+            decode,
+            encoding,
+        } => {
+            let arc = Arc::open_unchecked(&arc_file)?.with_name_encoding(encoding);
+            let mut issues = arc.check()?;
+            if decode {
+                issues.extend(arc.check_decode());
+            }
+            if !issues.is_empty() {
+                for issue in &issues {
+                    warn!("{issue}");
+                }
+                return Err(format!("{} problem(s) found", issues.len()).into());
+            }
+            info!(
+                "{}: {} entries, no problems found",
+                arc_file.display(),
+                arc.files_count()
+            );
+        }
     }
 
     Ok(())
@@ -121,8 +421,15 @@ mod tests {
             command: Commands::Pack {
                 input_dir,
                 output_file: Some(temp_dir_path.join("test.arc")),
-                version: ArcVersion::V2,
-                image: ImageFormat::Bgi,
+                version: Some(ArcVersion::V2),
+                image: None,
+                quality: DEFAULT_QUALITY,
+                grayscale: None,
+                dsc: false,
+                bse: Vec::new(),
+                bse_version: BseVersion::V100,
+                no_manifest: false,
+                encoding: NameEncoding::Cp932,
             },
         })
         .unwrap();
@@ -133,6 +440,11 @@ mod tests {
             command: Commands::Unpack {
                 arc_file: temp_dir_path.join("test.arc"),
                 output_path: Some(temp_dir_path.join("output")),
+                encoding: NameEncoding::Cp932,
+                max_in_flight: 1,
+                salvage: false,
+                no_manifest: false,
+                no_copy_payloads: false,
             },
         })
         .unwrap();
@@ -0,0 +1,823 @@
+//! Unpack manifest: how each extracted file was stored in the archive.
+//!
+//! [`crate::unpack_arc_with`] writes a [`MANIFEST_FILE`] next to the
+//! extracted files, recording the source archive's location and data region
+//! hash, plus by default a verbatim copy of the data region
+//! ([`PAYLOAD_FILE`]). [`crate::pack_arc_with`] reads it back to rebuild the
+//! archive: files that were not edited keep their original payload, read
+//! from the copy or else from the unchanged source archive, so an untouched
+//! directory packs into the input archive byte-for-byte, and edited files are
+//! re-encoded with the chain of formats they were unpacked from.
+//!
+//! The manifest is plain text. A `version` line gives the archive version
+//! and a `source` line the data region hash, offset and length, then the
+//! archive path. Each further line describes one entry, in index order, with
+//! tab-separated columns:
+//!
+//! - the extracted file name;
+//! - the format chain as space-separated [`Layer`] tokens, outermost first
+//!   (`-` for an entry stored as-is);
+//! - the file's [`content_hash`] (`-` if it could not be extracted);
+//! - the payload offset and size;
+//! - the index name field and padding in hex, trailing nulls removed (`-` if
+//!   empty).
+//!
+//! ```text
+//! version 2
+//! source 5e0c2f41d3a7b968 64 112640 /games/data01000.arc
+//! ev001a.png<TAB>bse-1.0 dsc image:bgi-scrambled:24<TAB>9c1b6e0a5d27f4e3<TAB>0<TAB>30752<TAB>657630303161<TAB>-
+//! se001.ogg<TAB>bw-ogg<TAB>04d2c8f1a9e3b760<TAB>30752<TAB>81920<TAB>7365303031<TAB>-
+//! ```
+//!
+//! Lines may stop after the chain; such entries only pick the formats their
+//! file is encoded with.
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+
+use crate::{
+    ImageFormat, PackOptions, PackReport,
+    arc::{ArcFile, ArcVersion, ArcWriter},
+    bse::BseVersion,
+    create_writer, entry_name,
+    error::{ArcError, ArcResult},
+    write::PixelLayout,
+};
+
+/// File name of the manifest inside an unpacked directory.
+pub const MANIFEST_FILE: &str = ".arc-manifest";
+
+/// File name of the copy of the archive's data region inside an unpacked
+/// directory.
+pub const PAYLOAD_FILE: &str = ".arc-payloads";
+
+/// Hash (64-bit FNV-1a) recorded for each extracted file, to tell edited
+/// files from untouched ones.
+#[must_use]
+pub fn content_hash(data: &[u8]) -> u64 {
+    fnv1a(0xCBF2_9CE4_8422_2325, data)
+}
+
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// [`content_hash`] of everything `reader` yields, and its length.
+fn hash_reader(mut reader: impl Read) -> io::Result<(u64, u64)> {
+    let mut buf = vec![0u8; 1 << 16];
+    let (mut hash, mut len) = (content_hash(&[]), 0u64);
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok((hash, len)),
+            Ok(n) => {
+                hash = fnv1a(hash, &buf[..n]);
+                len += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Incremental [`content_hash`] of an archive's data region, fed with the
+/// payloads in offset order so the region is read only once.
+pub(crate) struct RegionHash {
+    hash: u64,
+    /// Bytes of the region hashed so far.
+    len: u64,
+}
+
+impl RegionHash {
+    pub(crate) fn new() -> Self {
+        Self {
+            hash: content_hash(&[]),
+            len: 0,
+        }
+    }
+
+    /// Hash the payload `data` found at region offset `offset`, after the
+    /// bytes before it that no earlier payload covered. `read_at` reads the
+    /// region at an offset.
+    pub(crate) fn add(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+        read_at: impl FnMut(u64, &mut [u8]) -> io::Result<usize>,
+    ) -> io::Result<()> {
+        self.fill(offset, read_at)?;
+        let overlap = usize::try_from(self.len - offset).unwrap_or(usize::MAX);
+        if let Some(rest) = data.get(overlap..) {
+            self.hash = fnv1a(self.hash, rest);
+            self.len += rest.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Hash the rest of a region of length `end`, returning its hash.
+    pub(crate) fn finish(
+        mut self,
+        end: u64,
+        read_at: impl FnMut(u64, &mut [u8]) -> io::Result<usize>,
+    ) -> io::Result<u64> {
+        self.fill(end, read_at)?;
+        Ok(self.hash)
+    }
+
+    /// Hash the region up to `end` with `read_at`.
+    fn fill(
+        &mut self,
+        end: u64,
+        mut read_at: impl FnMut(u64, &mut [u8]) -> io::Result<usize>,
+    ) -> io::Result<()> {
+        let mut buf = vec![0u8; 1 << 16];
+        while self.len < end {
+            let want = usize::try_from(end - self.len).map_or(buf.len(), |n| n.min(buf.len()));
+            let n = read_at(self.len, &mut buf[..want])?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.hash = fnv1a(self.hash, &buf[..n]);
+            self.len += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// One layer of an entry's storage format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layer {
+    /// BSE encryption (`bse-1.0`, `bse-1.1`).
+    Bse(BseVersion),
+    /// DSC FORMAT 1.00 compression (`dsc`).
+    Dsc,
+    /// An image in the given format and pixel layout
+    /// (`image:<format>:<bpp>`, e.g. `image:cbg:24`).
+    Image(ImageFormat, PixelLayout),
+    /// OGG audio behind the BGI `bw  ` header (`bw-ogg`).
+    Audio,
+    /// A layer of a codec outside the built-in set, by its
+    /// [`crate::codec::Codec::name`].
+    Codec(String),
+}
+
+impl Layer {
+    /// Whether this layer holds decoded content rather than wrapping it.
+    #[must_use]
+    pub fn is_content(&self) -> bool {
+        matches!(self, Self::Image(..) | Self::Audio)
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bse(BseVersion::V100) => write!(f, "bse-1.0"),
+            Self::Bse(BseVersion::V101) => write!(f, "bse-1.1"),
+            Self::Dsc => write!(f, "dsc"),
+            Self::Image(format, layout) => write!(f, "image:{}:{}", format.name(), layout.bpp()),
+            Self::Audio => write!(f, "bw-ogg"),
+            Self::Codec(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Layer {
+    type Error = ArcError;
+
+    fn try_from(v: &str) -> Result<Self, Self::Error> {
+        let invalid = || ArcError::InvalidManifest(format!("unknown layer '{v}'"));
+        if let Some(version) = v.strip_prefix("bse-") {
+            return Ok(Self::Bse(BseVersion::try_from(version)?));
+        }
+        if let Some(image) = v.strip_prefix("image:") {
+            let (format, bpp) = image.rsplit_once(':').ok_or_else(invalid)?;
+            let layout = bpp
+                .parse()
+                .ok()
+                .and_then(PixelLayout::from_bpp)
+                .ok_or_else(invalid)?;
+            return Ok(Self::Image(ImageFormat::try_from(format)?, layout));
+        }
+        match v {
+            "dsc" => Ok(Self::Dsc),
+            "bw-ogg" => Ok(Self::Audio),
+            "" | "-" => Err(invalid()),
+            name => Ok(Self::Codec(name.to_owned())),
+        }
+    }
+}
+
+/// How one extracted file was stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Extracted file name, relative to the unpacked directory.
+    pub file: String,
+    /// Storage layers, outermost first; empty for data stored as-is.
+    pub chain: Vec<Layer>,
+    /// The entry's index fields and payload; `None` when only the chain is
+    /// recorded.
+    pub stored: Option<StoredEntry>,
+}
+
+/// Index fields and payload location of an entry, as found in the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredEntry {
+    /// Index name field, trailing nulls removed.
+    pub name: Vec<u8>,
+    /// Index bytes after the offset and size, trailing nulls removed.
+    pub padding: Vec<u8>,
+    /// Payload offset within the data region, and so within
+    /// [`PAYLOAD_FILE`].
+    pub offset: u32,
+    /// Payload size.
+    pub size: u32,
+    /// [`content_hash`] of the extracted file; `None` if the entry could not
+    /// be extracted, in which case its payload is always kept.
+    pub hash: Option<u64>,
+}
+
+impl StoredEntry {
+    pub(crate) fn new(file: &ArcFile, hash: Option<u64>) -> Self {
+        Self {
+            name: trim_nulls(&file.name).to_vec(),
+            padding: trim_nulls(&file.padding).to_vec(),
+            offset: file.offset,
+            size: file.size,
+            hash,
+        }
+    }
+
+    /// The index entry for this entry with its payload at `offset`.
+    fn index_entry(&self, version: ArcVersion, offset: u32, size: u32) -> ArcResult<ArcFile> {
+        let limit = version.name_len();
+        if self.name.len() > limit {
+            return Err(ArcError::FileNameTooLong(self.name.len(), limit));
+        }
+        if self.padding.len() > version.metadata_size() as usize - 8 - limit {
+            return Err(ArcError::InvalidManifest(format!(
+                "index padding of {} does not fit a {version:?} index",
+                String::from_utf8_lossy(&self.name)
+            )));
+        }
+        let mut name = self.name.clone();
+        name.resize(limit, 0);
+        Ok(ArcFile {
+            name,
+            offset,
+            size,
+            padding: self.padding.clone(),
+        })
+    }
+}
+
+/// The archive a directory was unpacked from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceArchive {
+    /// Absolute path of the archive.
+    pub path: PathBuf,
+    /// Start of its data region.
+    pub data_offset: u64,
+    /// Length of its data region.
+    pub data_len: u64,
+    /// [`content_hash`] of its data region.
+    pub hash: u64,
+}
+
+impl SourceArchive {
+    /// Record the archive at `path`, whose data region of `data_len` bytes
+    /// starts at `data_offset` and has the [`content_hash`] `hash`.
+    ///
+    /// Fails for paths the manifest cannot hold: not UTF-8, or with a line
+    /// break.
+    pub fn record(path: &Path, data_offset: u64, data_len: u64, hash: u64) -> ArcResult<Self> {
+        let path = fs::canonicalize(path)?;
+        if path.to_str().is_none_or(|path| path.contains(['\n', '\r'])) {
+            return Err(ArcError::InvalidManifest(format!(
+                "cannot record the path {}",
+                path.display()
+            )));
+        }
+        Ok(Self {
+            path,
+            data_offset,
+            data_len,
+            hash,
+        })
+    }
+
+    /// Open the archive, failing unless its data region is still the
+    /// recorded one: re-encoding every file instead would quietly produce a
+    /// different archive.
+    fn open(&self) -> ArcResult<Payloads> {
+        let unusable = |what| {
+            ArcError::InvalidManifest(format!(
+                "source archive {} {what} and there is no {PAYLOAD_FILE} to rebuild it from",
+                self.path.display()
+            ))
+        };
+        let Ok(mut file) = fs::File::open(&self.path) else {
+            return Err(unusable("is gone"));
+        };
+        if file.metadata()?.len() != self.data_offset + self.data_len
+            || hash_reader(Payloads::region(
+                &mut file,
+                self.data_offset,
+                self.data_len,
+            )?)?
+            .0 != self.hash
+        {
+            return Err(unusable("has changed"));
+        }
+        Ok(Payloads {
+            file,
+            start: self.data_offset,
+            len: self.data_len,
+        })
+    }
+}
+
+/// The original data region a rebuild copies untouched payloads from.
+pub(crate) struct Payloads {
+    file: fs::File,
+    /// Where the data region starts in `file`.
+    start: u64,
+    len: u64,
+}
+
+impl Payloads {
+    /// `len` bytes of `file` from `start`.
+    fn region(file: &mut fs::File, start: u64, len: u64) -> io::Result<io::Take<&fs::File>> {
+        file.seek(SeekFrom::Start(start))?;
+        Ok((&*file).take(len))
+    }
+}
+
+/// Contents of a [`MANIFEST_FILE`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Version of the unpacked archive; recorded when the archive can be
+    /// rebuilt.
+    pub version: Option<ArcVersion>,
+    /// The archive that supplies untouched payloads when there is no
+    /// [`PAYLOAD_FILE`].
+    pub source: Option<SourceArchive>,
+    /// Entries in archive order.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// What [`Manifest::rebuild`] does with a recorded entry.
+enum Plan<'a> {
+    /// Copy the original payload.
+    Keep,
+    /// Re-encode the extracted file.
+    Encode(&'a Path),
+    /// Leave the entry out; its file was deleted.
+    Drop,
+}
+
+impl Manifest {
+    /// Read the manifest of an unpacked directory, if it has one.
+    pub fn read(dir: impl AsRef<Path>) -> ArcResult<Option<Self>> {
+        let path = dir.as_ref().join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Self::parse(&fs::read_to_string(path)?).map(Some)
+    }
+
+    /// Write the manifest into an unpacked directory.
+    pub fn write(&self, dir: impl AsRef<Path>) -> ArcResult<()> {
+        fs::write(dir.as_ref().join(MANIFEST_FILE), self.to_string())?;
+        Ok(())
+    }
+
+    /// Parse manifest text; blank lines and `#` comments are ignored.
+    pub fn parse(text: &str) -> ArcResult<Self> {
+        let mut manifest = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let n = n + 1;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |what: &str| ArcError::InvalidManifest(format!("line {n}: {what}"));
+            let mut columns = line.split('\t');
+            let file = columns.next().unwrap_or_default();
+            let Some(chain) = columns.next() else {
+                match line.split_once(' ') {
+                    Some(("version", "1")) => manifest.version = Some(ArcVersion::V1),
+                    Some(("version", "2")) => manifest.version = Some(ArcVersion::V2),
+                    Some(("version", _)) => return Err(invalid("unknown archive version")),
+                    Some(("source", source)) => {
+                        let bad = || invalid("bad source line");
+                        let mut fields = source.splitn(4, ' ');
+                        let mut number = |radix| {
+                            let field = fields.next().ok_or_else(bad)?;
+                            u64::from_str_radix(field, radix).map_err(|_| bad())
+                        };
+                        let (hash, data_offset, data_len) = (number(16)?, number(10)?, number(10)?);
+                        manifest.source = Some(SourceArchive {
+                            path: PathBuf::from(fields.next().ok_or_else(bad)?),
+                            data_offset,
+                            data_len,
+                            hash,
+                        });
+                    }
+                    _ => return Err(invalid("no tab")),
+                }
+                continue;
+            };
+            let chain = match chain.trim() {
+                "-" => Vec::new(),
+                tokens => tokens
+                    .split_whitespace()
+                    .map(Layer::try_from)
+                    .collect::<ArcResult<Vec<_>>>()?,
+            };
+            if chain.iter().rev().skip(1).any(Layer::is_content) {
+                return Err(invalid("only the last layer may be an image or audio"));
+            }
+            let stored = match columns.collect::<Vec<_>>()[..] {
+                [] => None,
+                [hash, offset, size, name, padding] => Some(StoredEntry {
+                    name: unhex(name).ok_or_else(|| invalid("bad name field"))?,
+                    padding: unhex(padding).ok_or_else(|| invalid("bad index padding"))?,
+                    offset: offset.parse().map_err(|_| invalid("bad offset"))?,
+                    size: size.parse().map_err(|_| invalid("bad size"))?,
+                    hash: match hash {
+                        "-" => None,
+                        hash => {
+                            Some(u64::from_str_radix(hash, 16).map_err(|_| invalid("bad hash"))?)
+                        }
+                    },
+                }),
+                _ => return Err(invalid("expected 2 or 7 columns")),
+            };
+            manifest.entries.push(ManifestEntry {
+                file: file.to_owned(),
+                chain,
+                stored,
+            });
+        }
+        Ok(manifest)
+    }
+
+    /// The entry recorded for the extracted file `file`.
+    #[must_use]
+    pub fn get(&self, file: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.file == file)
+    }
+
+    /// The original payloads of the archive unpacked into `dir`, from its
+    /// [`PAYLOAD_FILE`] or else the source archive; `None` if
+    /// [`Manifest::rebuild`] cannot rebuild it.
+    ///
+    /// Fails if the payloads must come from the source archive and it is
+    /// gone or has changed.
+    pub(crate) fn payloads(&self, dir: &Path) -> ArcResult<Option<Payloads>> {
+        if self.version.is_none() {
+            return Ok(None);
+        }
+        let copy = dir.join(PAYLOAD_FILE);
+        if copy.is_file() {
+            let file = fs::File::open(copy)?;
+            let len = file.metadata()?.len();
+            return Ok(Some(Payloads {
+                file,
+                start: 0,
+                len,
+            }));
+        }
+        self.source.as_ref().map(SourceArchive::open).transpose()
+    }
+
+    /// Decide what [`Manifest::rebuild`] does with each entry, comparing the
+    /// extracted `files` against their recorded hashes. With `reencode_images`
+    /// every extracted image is re-encoded, edited or not.
+    fn plan<'a>(&self, files: &'a [PathBuf], reencode_images: bool) -> ArcResult<Vec<Plan<'a>>> {
+        let present: HashMap<_, _> = files
+            .iter()
+            .filter_map(|path| Some((path.file_name()?.to_string_lossy(), path.as_path())))
+            .collect();
+        let mut hashes = HashMap::new();
+        let mut plans = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let Some(stored) = &entry.stored else {
+                // Chain-only entries are packed like files not in the
+                // manifest.
+                plans.push(Plan::Drop);
+                continue;
+            };
+            let Some(recorded) = stored.hash else {
+                plans.push(Plan::Keep);
+                continue;
+            };
+            let Some(&path) = present.get(entry.file.as_str()) else {
+                info!("{} was deleted, dropping its entry", entry.file);
+                plans.push(Plan::Drop);
+                continue;
+            };
+            let hash = if let Some(&hash) = hashes.get(entry.file.as_str()) {
+                hash
+            } else {
+                let hash = content_hash(&fs::read(path)?);
+                hashes.insert(entry.file.as_str(), hash);
+                hash
+            };
+            // Same-named entries share one extracted file, which holds only
+            // one of their payloads.
+            let untouched = hash == recorded
+                || self.entries.iter().any(|other| {
+                    other.file == entry.file
+                        && other.stored.as_ref().and_then(|stored| stored.hash) == Some(hash)
+                });
+            let image = matches!(entry.chain.last(), Some(Layer::Image(..)));
+            plans.push(if untouched && !(reencode_images && image) {
+                Plan::Keep
+            } else {
+                Plan::Encode(path)
+            });
+        }
+        Ok(plans)
+    }
+
+    /// Rebuild the archive unpacked into `dir` from its extracted `files`, as
+    /// a `version` archive.
+    ///
+    /// Entries whose file is unchanged or was never extracted get their
+    /// original payload, and the bytes between payloads are kept too;
+    /// edited files (and, with an explicit image format, every extracted
+    /// image) are re-encoded and entries whose file was deleted are dropped.
+    /// Files the manifest does not cover are appended, in the order given.
+    pub(crate) fn rebuild(
+        &self,
+        mut payloads: Payloads,
+        files: &[PathBuf],
+        output: &Path,
+        version: ArcVersion,
+        options: &PackOptions,
+    ) -> ArcResult<PackReport> {
+        let plans = self.plan(files, options.encode.image_format.is_some())?;
+        let new_files: Vec<_> = files
+            .iter()
+            .filter(|path| {
+                let file = path.file_name().unwrap_or_default().to_string_lossy();
+                !self
+                    .entries
+                    .iter()
+                    .any(|entry| entry.stored.is_some() && entry.file == file)
+            })
+            .collect();
+        let count = plans
+            .iter()
+            .filter(|plan| !matches!(plan, Plan::Drop))
+            .count()
+            + new_files.len();
+        let mut writer = create_writer(output, version, options, count)?;
+        let payloads_len = payloads.len;
+        let mut copy = |writer: &mut ArcWriter<fs::File>, start: u64, len: u64| {
+            let region = Payloads::region(&mut payloads.file, payloads.start + start, len)?;
+            let (offset, size) = writer.add_data(region)?;
+            if u64::from(size) < len {
+                return Err(ArcError::InvalidManifest(format!(
+                    "the original data region ends before byte {}",
+                    start + len
+                )));
+            }
+            Ok((offset, size))
+        };
+
+        // Payloads go out in their original order, each followed by the
+        // bytes up to the next one.
+        let stored_at = |i: usize| self.entries[i].stored.as_ref();
+        let mut order: Vec<_> = (0..self.entries.len())
+            .filter(|&i| stored_at(i).is_some())
+            .collect();
+        order.sort_by_key(|&i| stored_at(i).map(|s| (s.offset, s.size)));
+        let start = |i: usize| stored_at(i).map_or(payloads_len, |s| u64::from(s.offset));
+
+        copy(
+            &mut writer,
+            0,
+            order.first().map_or(payloads_len, |&i| start(i)),
+        )?;
+        let mut placed: Vec<Option<(u32, u32)>> = vec![None; self.entries.len()];
+        let mut report = PackReport::default();
+        for (k, &i) in order.iter().enumerate() {
+            let entry = &self.entries[i];
+            let Some(stored) = &entry.stored else {
+                continue;
+            };
+            let (offset, end) = (
+                u64::from(stored.offset),
+                u64::from(stored.offset) + u64::from(stored.size),
+            );
+            placed[i] = match plans[i] {
+                Plan::Drop => None,
+                // Entries sharing one payload keep sharing it.
+                Plan::Keep
+                    if k > 0
+                        && matches!(plans[order[k - 1]], Plan::Keep)
+                        && stored_at(order[k - 1]).is_some_and(|prev| {
+                            (prev.offset, prev.size) == (stored.offset, stored.size)
+                        }) =>
+                {
+                    placed[order[k - 1]]
+                }
+                Plan::Keep => {
+                    debug!("{}: keeping the original payload", entry.file);
+                    Some(copy(&mut writer, offset, end - offset)?)
+                }
+                Plan::Encode(path) => {
+                    info!("re-encoding file: {}", path.display());
+                    let name = options.name_encoding.decode(&stored.name);
+                    let data = fs::read(path)?;
+                    let encoded =
+                        options.encode_entry(&name, &data, Some(&entry.chain), &mut report)?;
+                    Some(writer.add_data(encoded.as_slice())?)
+                }
+            };
+            let next = order.get(k + 1).map_or(payloads_len, |&j| start(j));
+            if next > end {
+                copy(&mut writer, end, next - end)?;
+            }
+        }
+
+        for (entry, placed) in self.entries.iter().zip(&placed) {
+            if let (Some(stored), Some((offset, size))) = (&entry.stored, placed) {
+                writer.add_index_entry(stored.index_entry(version, *offset, *size)?)?;
+            }
+        }
+        for path in new_files {
+            info!("adding file: {}", path.display());
+            let name = entry_name(path)?;
+            let file = path.file_name().unwrap_or_default().to_string_lossy();
+            let chain = self.get(&file).map(|entry| entry.chain.as_slice());
+            let data = options.encode_entry(&name, &fs::read(path)?, chain, &mut report)?;
+            writer.add_encoded(&name, data.as_slice())?;
+        }
+        writer.finish()?;
+        Ok(report)
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "# arc-reader manifest: file, format chain (outermost first), hash, offset, size, name, padding"
+        )?;
+        match self.version {
+            Some(ArcVersion::V1) => writeln!(f, "version 1")?,
+            Some(ArcVersion::V2) => writeln!(f, "version 2")?,
+            None => {}
+        }
+        if let Some(source) = &self.source {
+            writeln!(
+                f,
+                "source {:016x} {} {} {}",
+                source.hash,
+                source.data_offset,
+                source.data_len,
+                source.path.display()
+            )?;
+        }
+        for entry in &self.entries {
+            write!(f, "{}\t", entry.file)?;
+            if entry.chain.is_empty() {
+                write!(f, "-")?;
+            }
+            for (i, layer) in entry.chain.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{layer}")?;
+            }
+            if let Some(stored) = &entry.stored {
+                match stored.hash {
+                    Some(hash) => write!(f, "\t{hash:016x}")?,
+                    None => write!(f, "\t-")?,
+                }
+                write!(
+                    f,
+                    "\t{}\t{}\t{}\t{}",
+                    stored.offset,
+                    stored.size,
+                    Hex(&stored.name),
+                    Hex(&stored.padding)
+                )?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn trim_nulls(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &bytes[..len]
+}
+
+/// Lowercase hex, or `-` for no bytes.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "-");
+        }
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text == "-" {
+        return Some(Vec::new());
+    }
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let manifest = Manifest {
+            version: Some(ArcVersion::V1),
+            source: Some(SourceArchive {
+                path: PathBuf::from("/games/my data.arc"),
+                data_offset: 144,
+                data_len: 342,
+                hash: 0x00AB_CDEF,
+            }),
+            entries: vec![
+                ManifestEntry {
+                    file: "ev 001.png".to_owned(),
+                    chain: vec![
+                        Layer::Bse(BseVersion::V101),
+                        Layer::Dsc,
+                        Layer::Image(ImageFormat::BgiScrambled, PixelLayout::Gray),
+                    ],
+                    stored: Some(StoredEntry {
+                        name: b"ev\x82\xa0".to_vec(),
+                        padding: Vec::new(),
+                        offset: 0,
+                        size: 300,
+                        hash: Some(0x0123_4567_89AB_CDEF),
+                    }),
+                },
+                ManifestEntry {
+                    file: "se001.ogg".to_owned(),
+                    chain: vec![Layer::Audio],
+                    stored: Some(StoredEntry {
+                        name: b"se001".to_vec(),
+                        padding: vec![0, 7],
+                        offset: 300,
+                        size: 42,
+                        hash: None,
+                    }),
+                },
+                ManifestEntry {
+                    file: "script".to_owned(),
+                    chain: Vec::new(),
+                    stored: None,
+                },
+                ManifestEntry {
+                    file: "custom".to_owned(),
+                    chain: vec![Layer::Codec("xor".to_owned()), Layer::Dsc],
+                    stored: None,
+                },
+            ],
+        };
+        let text = manifest.to_string();
+        assert!(text.contains("\nversion 1\nsource 0000000000abcdef 144 342 /games/my data.arc\n"));
+        assert!(text.contains(
+            "ev 001.png\tbse-1.1 dsc image:bgi-scrambled:8\t0123456789abcdef\t0\t300\t657682a0\t-\n"
+        ));
+        assert!(text.contains("se001.ogg\tbw-ogg\t-\t300\t42\t7365303031\t0007\n"));
+        assert!(text.contains("script\t-\n"));
+        assert_eq!(Manifest::parse(&text).unwrap(), manifest);
+
+        assert!(Manifest::parse("a.png\timage:cbg:24 dsc\n").is_err());
+        assert!(Manifest::parse("a.png\timage:cbg:16\n").is_err());
+        assert!(Manifest::parse("a.png\n").is_err());
+        assert!(Manifest::parse("version 3\n").is_err());
+        assert!(Manifest::parse("source 00ff 0 1\n").is_err());
+        assert!(Manifest::parse("a.png\t-\t-\t0\t1\n").is_err());
+        assert!(Manifest::parse("a.png\t-\t-\t0\t1\t6\t-\n").is_err());
+    }
+}
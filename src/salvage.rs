@@ -0,0 +1,325 @@
+//! Best-effort recovery of truncated or partially corrupted archives.
+//!
+//! [`Arc::salvage`] keeps every index entry whose data is still inside the
+//! file and drops the rest. A declared entry count too large for the file is
+//! replaced by the number of index slots that look like entries, so a
+//! corrupted count does not hide the data region. The parts of the data
+//! region no surviving entry covers are then scanned for payload signatures
+//! (`CompressedBG___`, `DSC FORMAT 1.00`, `BSE 1.` and the `bw  ` audio
+//! header); each hit becomes an extra entry named
+//! `orphan_<absolute offset in hex>` that runs up to the next hit or the end
+//! of the uncovered span.
+//!
+//! Orphan boundaries are guesses: a payload without a signature (e.g. a raw
+//! BGI image) that follows an orphan is absorbed into the orphan's tail.
+
+// ARC format uses u32 for offsets/sizes; intentional truncation in format code.
+#![allow(clippy::cast_possible_truncation)]
+
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use log::warn;
+
+use crate::{
+    arc::{Arc, ArcFile, ArcVersion, index_len, trim_name},
+    error::{ArcError, ArcResult},
+    ogg,
+};
+
+/// Signatures that mark the start of a payload. The audio header's `bw  `
+/// sits 4 bytes into the payload.
+const SIGNATURES: [&[u8]; 4] = [b"CompressedBG___", b"DSC FORMAT 1.00", b"BSE 1.", b"bw  "];
+
+/// Bytes scanned per read while looking for signatures.
+const SCAN_CHUNK: usize = 1 << 20;
+
+/// What [`Arc::salvage`] had to drop or add.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// Entry count declared by the header.
+    pub declared: u32,
+    /// Entry count found by scanning the index, when the declared count did
+    /// not fit the file and the index ended before it.
+    pub detected: Option<u32>,
+    /// Index slots that were cut off by the end of the file.
+    pub unreadable: u32,
+    /// Readable index entries dropped because their data is out of range.
+    pub skipped: Vec<u32>,
+    /// Orphaned payloads recovered from the data region. They follow the
+    /// surviving index entries in the salvaged archive.
+    pub recovered: u32,
+}
+
+impl Arc<File> {
+    /// Open an ARC file in salvage mode. See [`Arc::salvage`].
+    pub fn open_salvage<P: AsRef<Path>>(filename: P) -> ArcResult<(Self, SalvageReport)> {
+        Self::salvage(File::open(&filename)?)
+    }
+}
+
+impl<R: Read + Seek> Arc<R> {
+    /// Parse as much of a damaged archive as possible, starting at the
+    /// reader's current position.
+    ///
+    /// Only the magic signature has to be intact. Index entries that are cut
+    /// off or point past the end of the stream are dropped, and orphaned
+    /// payloads found in the uncovered parts of the data region are added
+    /// under generated names.
+    pub fn salvage(mut reader: R) -> ArcResult<(Self, SalvageReport)> {
+        let base = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(base))?;
+
+        let mut magic_string = [0u8; 12];
+        reader.read_exact(&mut magic_string)?;
+        let version = ArcVersion::from_magic(&magic_string).ok_or(ArcError::InvalidFormat)?;
+
+        let mut buffer = [0u8; 4];
+        let declared = match reader.read_exact(&mut buffer) {
+            Ok(()) => u32::from_le_bytes(buffer),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        let room = end.saturating_sub(base + 16) / u64::from(version.metadata_size());
+        let readable = room.min(u64::from(declared)) as u32;
+        let mut slots = Vec::with_capacity(readable as usize);
+        for _ in 0..readable {
+            slots.push(Self::read_metadata(&mut reader, version)?);
+        }
+
+        let mut report = SalvageReport {
+            declared,
+            unreadable: declared - readable,
+            ..SalvageReport::default()
+        };
+        // A count that overshoots the file is either a cut-off index or a
+        // corrupted count. In the latter case the index ends at the first
+        // slot that is really payload data.
+        let data_len = end.saturating_sub(base + 16);
+        let indexed = slots
+            .iter()
+            .take_while(|slot| plausible_slot(slot, data_len))
+            .count();
+        if report.unreadable > 0 && indexed < slots.len() {
+            warn!("declared entry count {declared} is corrupt, found {indexed} index entries");
+            slots.truncate(indexed);
+            report.detected = Some(indexed as u32);
+            report.unreadable = 0;
+        } else if report.unreadable > 0 {
+            warn!("{} index entries are cut off", report.unreadable);
+        }
+        let count = report.detected.unwrap_or(declared);
+        let data_offset = base + index_len(version, u64::from(count));
+
+        let mut files = Vec::with_capacity(slots.len());
+        for (i, file) in slots.into_iter().enumerate() {
+            let start = data_offset + u64::from(file.offset);
+            if start + u64::from(file.size) <= end {
+                files.push(file);
+            } else {
+                warn!("skipping entry {i}: data at {start:#x} is out of range");
+                report.skipped.push(i as u32);
+            }
+        }
+
+        for (from, to) in uncovered(&files, data_offset, end) {
+            let starts = find_payloads(&mut reader, from, to)?;
+            for (k, &start) in starts.iter().enumerate() {
+                let stop = starts.get(k + 1).copied().unwrap_or(to);
+                let (Ok(offset), Ok(size)) = (
+                    u32::try_from(start - data_offset),
+                    u32::try_from(stop - start),
+                ) else {
+                    continue;
+                };
+                let mut name = format!("orphan_{start:08x}").into_bytes();
+                name.resize(version.name_len(), 0);
+                files.push(ArcFile {
+                    name,
+                    offset,
+                    size,
+                    padding: Vec::new(),
+                });
+                report.recovered += 1;
+            }
+        }
+
+        let arc = Self::from_parts(reader, version, data_offset, end, files);
+        Ok((arc, report))
+    }
+}
+
+/// Whether an index slot looks like an entry rather than payload data: a
+/// printable name that is not a payload signature, and a range that fits
+/// in `data_len` bytes.
+fn plausible_slot(file: &ArcFile, data_len: u64) -> bool {
+    let name = trim_name(&file.name);
+    !name.is_empty()
+        && name.iter().all(|&b| b >= 0x20)
+        && !SIGNATURES.iter().any(|sig| name.starts_with(sig))
+        && u64::from(file.offset) + u64::from(file.size) <= data_len
+}
+
+/// Absolute `[from, to)` spans of the data region not covered by any entry.
+fn uncovered(files: &[ArcFile], data_offset: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut covered: Vec<(u64, u64)> = files
+        .iter()
+        .map(|f| {
+            let start = data_offset + u64::from(f.offset);
+            (start, start + u64::from(f.size))
+        })
+        .collect();
+    covered.sort_unstable();
+
+    let mut spans = Vec::new();
+    let mut cursor = data_offset;
+    for (start, stop) in covered {
+        if start > cursor {
+            spans.push((cursor, start));
+        }
+        cursor = cursor.max(stop);
+    }
+    if end > cursor {
+        spans.push((cursor, end));
+    }
+    spans
+}
+
+/// Absolute start positions of payloads whose signature lies in `[from, to)`.
+fn find_payloads<R: Read + Seek>(reader: &mut R, from: u64, to: u64) -> ArcResult<Vec<u64>> {
+    let overlap = SIGNATURES.iter().map(|s| s.len()).max().unwrap_or(0) - 1;
+    let mut buf = vec![0u8; SCAN_CHUNK + overlap];
+    let mut hits = Vec::new();
+
+    let mut pos = from;
+    while pos < to {
+        let len = usize::try_from(to - pos).map_or(buf.len(), |n| n.min(buf.len()));
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut buf[..len])?;
+        for i in 0..len.min(SCAN_CHUNK) {
+            let rest = &buf[i..len];
+            if let Some(sig) = SIGNATURES.iter().find(|sig| rest.starts_with(sig)) {
+                let hit = pos + i as u64;
+                if *sig == b"bw  " {
+                    if hit >= from + 4 {
+                        hits.push((hit - 4, true));
+                    }
+                } else {
+                    hits.push((hit, false));
+                }
+            }
+        }
+        pos += SCAN_CHUNK as u64;
+    }
+
+    // `bw  ` is short enough to show up by chance; keep it only if the audio
+    // header actually points at an Ogg stream.
+    let mut starts = Vec::with_capacity(hits.len());
+    let mut header = vec![0u8; 0x1000];
+    for (start, is_audio) in hits {
+        if is_audio {
+            let len = usize::try_from(to - start).map_or(header.len(), |n| n.min(header.len()));
+            reader.seek(SeekFrom::Start(start))?;
+            reader.read_exact(&mut header[..len])?;
+            if !ogg::is_bgi_ogg(&header[..len]) {
+                continue;
+            }
+        }
+        starts.push(start);
+    }
+    starts.sort_unstable();
+    Ok(starts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::arc::ArcWriter;
+
+    #[test]
+    fn test_salvage_truncated() {
+        let cbg = include_bytes!("../test_assets/fixtures/arc_cbg.arc");
+        let cbg = Arc::from_bytes(&cbg[..]).unwrap().get_file_data(0).unwrap();
+        let dsc = [b"DSC FORMAT 1.00\0".as_slice(), &[7u8; 48]].concat();
+
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry_raw(b"text", b"plain text").unwrap();
+        writer.add_entry_raw(b"script", &dsc).unwrap();
+        writer.add_entry_raw(b"image", &cbg).unwrap();
+        let mut data = writer.finish().unwrap().into_inner();
+
+        // Corrupt the index slot of "script" and cut the tail off "image".
+        data[16 + 32 + 16..16 + 32 + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        data.truncate(data.len() - 10);
+        assert!(Arc::from_bytes(data.clone()).is_err());
+
+        let (arc, report) = Arc::salvage(Cursor::new(data)).unwrap();
+        assert_eq!(report.declared, 3);
+        assert_eq!(report.unreadable, 0);
+        assert_eq!(report.skipped, [1, 2]);
+        assert_eq!(report.recovered, 2);
+
+        let data_offset = 16 + 3 * 32;
+        assert_eq!(arc.files_count(), 3);
+        assert_eq!(arc.get_file_name(0).unwrap(), "text");
+        assert_eq!(arc.get_file_data(0).unwrap(), b"plain text");
+        assert_eq!(
+            arc.get_file_name(1).unwrap(),
+            format!("orphan_{:08x}", data_offset + 10)
+        );
+        assert_eq!(arc.get_file_data(1).unwrap(), dsc);
+        assert_eq!(arc.get_file_data(2).unwrap(), &cbg[..cbg.len() - 10]);
+    }
+
+    #[test]
+    fn test_salvage_corrupt_count() {
+        let cbg = include_bytes!("../test_assets/fixtures/arc_cbg.arc");
+        let cbg = Arc::from_bytes(&cbg[..]).unwrap().get_file_data(0).unwrap();
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry_raw(b"text", b"plain text").unwrap();
+        writer.add_entry_raw(b"image", &cbg).unwrap();
+        let mut data = writer.finish().unwrap().into_inner();
+        data[12..16].copy_from_slice(&0x00FF_FFFFu32.to_le_bytes());
+        assert!(Arc::from_bytes(data.clone()).is_err());
+
+        let (arc, report) = Arc::salvage(Cursor::new(data)).unwrap();
+        assert_eq!(report.detected, Some(2));
+        assert_eq!(report.unreadable, 0);
+        assert_eq!(report.skipped, []);
+        assert_eq!(arc.files_count(), 2);
+        assert_eq!(arc.get_file_data(0).unwrap(), b"plain text");
+        assert_eq!(arc.get_file_data(1).unwrap(), cbg);
+
+        // With no plausible slot at all, the whole file after the header is
+        // scanned for payloads.
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry_raw(b"\x01bad", &cbg).unwrap();
+        let mut data = writer.finish().unwrap().into_inner();
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        let (arc, report) = Arc::salvage(Cursor::new(data)).unwrap();
+        assert_eq!(report.detected, Some(0));
+        assert_eq!(report.recovered, 1);
+        assert_eq!(arc.get_file_data(0).unwrap(), cbg);
+    }
+
+    #[test]
+    fn test_salvage_cut_index() {
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V2).unwrap();
+        writer.add_entry_raw(b"a", b"a").unwrap();
+        writer.add_entry_raw(b"b", b"b").unwrap();
+        let mut data = writer.finish().unwrap().into_inner();
+        data.truncate(16 + 128 + 50);
+
+        let (arc, report) = Arc::salvage(Cursor::new(data)).unwrap();
+        assert_eq!(report.unreadable, 1);
+        assert_eq!(report.skipped, [0]);
+        assert_eq!(arc.files_count(), 0);
+    }
+}
@@ -0,0 +1,424 @@
+//! Structural integrity checks for ARC archives.
+//!
+//! [`Arc::open`] trusts the index; [`Arc::check`] cross-checks it against the
+//! archive length and itself, and [`Arc::check_decode`] additionally runs every
+//! entry through [`crate::decode_file`]'s detection chain.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Seek},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    arc::{Arc, ArcFile, trim_name},
+    decode_bytes,
+    error::ArcResult,
+};
+
+/// A single problem found by [`Arc::check`] or [`Arc::check_decode`].
+///
+/// Offsets are absolute positions within the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The header's entry count disagrees with what the index looks like.
+    CountMismatch { declared: u32, detected: u32 },
+    /// The entry's data extends past the end of the archive.
+    OutOfBounds {
+        index: u32,
+        offset: u64,
+        size: u32,
+        archive_len: u64,
+    },
+    /// The entry's data overlaps an earlier entry's data.
+    Overlap { index: u32, other: u32, offset: u64 },
+    /// The entry has the same raw name as an earlier entry.
+    DuplicateName {
+        index: u32,
+        first: u32,
+        name: String,
+    },
+    /// Bytes of the data region not covered by any entry.
+    Gap { offset: u64, len: u64 },
+    /// The entry was recognized but failed to decode.
+    DecodeFailed {
+        index: u32,
+        offset: u64,
+        error: String,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CountMismatch { declared, detected } => write!(
+                f,
+                "header declares {declared} entries, index looks like {detected}"
+            ),
+            Self::OutOfBounds {
+                index,
+                offset,
+                size,
+                archive_len,
+            } => write!(
+                f,
+                "entry {index}: {size} bytes at {offset:#x} extend past archive end {archive_len:#x}"
+            ),
+            Self::Overlap {
+                index,
+                other,
+                offset,
+            } => write!(
+                f,
+                "entry {index}: data at {offset:#x} overlaps entry {other}"
+            ),
+            Self::DuplicateName { index, first, name } => {
+                write!(
+                    f,
+                    "entry {index}: name '{name}' already used by entry {first}"
+                )
+            }
+            Self::Gap { offset, len } => write!(f, "{len} unused bytes at {offset:#x}"),
+            Self::DecodeFailed {
+                index,
+                offset,
+                error,
+            } => write!(f, "entry {index} at {offset:#x}: decode failed: {error}"),
+        }
+    }
+}
+
+impl<R: Read + Seek> Arc<R> {
+    /// Check the index for structural problems: entries past the end of the
+    /// archive, overlapping entries, duplicate names, unused gaps, and an entry
+    /// count that does not match the index.
+    ///
+    /// Returns an empty list for a well-formed archive.
+    pub fn check(&self) -> ArcResult<Vec<Issue>> {
+        let files = self.files();
+        let data_offset = self.data_offset();
+        let archive_len = self.stream_end();
+        let mut issues = Vec::new();
+
+        if let Some(detected) = self.detect_count(archive_len)? {
+            issues.push(Issue::CountMismatch {
+                declared: files.len() as u32,
+                detected,
+            });
+        }
+
+        let mut seen: HashMap<&[u8], u32> = HashMap::new();
+        for (i, file) in files.iter().enumerate() {
+            let index = i as u32;
+            let offset = data_offset + u64::from(file.offset);
+            if offset + u64::from(file.size) > archive_len {
+                issues.push(Issue::OutOfBounds {
+                    index,
+                    offset,
+                    size: file.size,
+                    archive_len,
+                });
+            }
+            if let Some(&first) = seen.get(trim_name(&file.name)) {
+                issues.push(Issue::DuplicateName {
+                    index,
+                    first,
+                    name: self.get_file_name(index)?.to_owned(),
+                });
+            } else {
+                seen.insert(trim_name(&file.name), index);
+            }
+        }
+
+        // Walk entries in data order to find overlaps and gaps.
+        let mut order: Vec<u32> = (0..files.len() as u32)
+            .filter(|&i| files[i as usize].size > 0)
+            .collect();
+        order.sort_by_key(|&i| files[i as usize].offset);
+
+        let mut cursor = data_offset;
+        let mut furthest: Option<u32> = None;
+        for index in order {
+            let file = &files[index as usize];
+            let start = data_offset + u64::from(file.offset);
+            let end = (start + u64::from(file.size)).min(archive_len);
+            if start < cursor {
+                if let Some(other) = furthest {
+                    issues.push(Issue::Overlap {
+                        index,
+                        other,
+                        offset: start,
+                    });
+                }
+            } else if start > cursor {
+                issues.push(Issue::Gap {
+                    offset: cursor,
+                    len: start - cursor,
+                });
+            }
+            if end > cursor {
+                cursor = end;
+                furthest = Some(index);
+            }
+        }
+        if archive_len > cursor {
+            issues.push(Issue::Gap {
+                offset: cursor,
+                len: archive_len - cursor,
+            });
+        }
+
+        Ok(issues)
+    }
+
+    /// Try to decode every entry through [`crate::decode_file`]'s detection
+    /// chain (BSE → DSC → CBG → BGI → OGG) and report codec failures.
+    #[must_use]
+    pub fn check_decode(&self) -> Vec<Issue>
+    where
+        R: Send,
+    {
+        let data_offset = self.data_offset();
+        let mut issues: Vec<Issue> = (0..self.files_count())
+            .into_par_iter()
+            .filter_map(|index| {
+                let offset = data_offset + u64::from(self.files()[index as usize].offset);
+                let result = self
+                    .get_file_data(index)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| decode_bytes(&data).map(drop).map_err(|e| e.to_string()));
+                result.err().map(|error| Issue::DecodeFailed {
+                    index,
+                    offset,
+                    error,
+                })
+            })
+            .collect();
+        issues.sort_by_key(|issue| match issue {
+            Issue::DecodeFailed { index, .. } => *index,
+            _ => 0,
+        });
+        issues
+    }
+
+    /// Guess the real entry count when the header's count looks wrong.
+    ///
+    /// Too small a count leaves plausible index slots right after the index,
+    /// recognized when their payloads continue the declared entries' layout;
+    /// too large a count turns the start of the data region into garbage
+    /// trailing entries. Returns `None` if the count looks right.
+    fn detect_count(&self, archive_len: u64) -> ArcResult<Option<u32>> {
+        let files = self.files();
+        let version = self.version();
+        let data_offset = self.data_offset();
+        let slot_len = version.metadata_size() as usize;
+        let data_len = archive_len.saturating_sub(data_offset);
+
+        let named = |file: &ArcFile| {
+            let name = trim_name(&file.name);
+            !name.is_empty() && name.iter().all(|&b| b >= 0x20)
+        };
+        let plausible = |file: &ArcFile| {
+            named(file) && u64::from(file.offset) + u64::from(file.size) <= data_len
+        };
+
+        // Only the name tells garbage apart: a real entry with a bad range
+        // is reported as out of bounds instead.
+        let garbage = files.iter().rev().take_while(|f| !named(f)).count();
+        if garbage > 0 {
+            return Ok(Some((files.len() - garbage) as u32));
+        }
+
+        // Slots hidden by too small a count: plausible entries with the
+        // zero padding the index always carries, each payload continuing
+        // where the previous entry's ends. The layout check keeps payload
+        // headers that merely look like a slot (CBG's name-like magic,
+        // dimensions and reserved zeros) from counting.
+        let name_len = version.name_len();
+        let mut extra = 0u32;
+        let mut slot = vec![0u8; slot_len];
+        let mut pos = data_offset;
+        let mut prev_end = files
+            .last()
+            .map_or(0, |f| u64::from(f.offset) + u64::from(f.size));
+        while self.read_at(pos, &mut slot)? == slot_len {
+            let file = ArcFile {
+                name: slot[..name_len].to_vec(),
+                offset: u32::from_le_bytes(slot[name_len..name_len + 4].try_into().unwrap()),
+                size: u32::from_le_bytes(slot[name_len + 4..name_len + 8].try_into().unwrap()),
+                padding: slot[name_len + 8..].to_vec(),
+            };
+            let end = u64::from(file.offset) + u64::from(file.size);
+            let room = data_len.saturating_sub((u64::from(extra) + 1) * slot_len as u64);
+            if !plausible(&file)
+                || file.padding.iter().any(|&b| b != 0)
+                || u64::from(file.offset) != prev_end
+                || end > room
+            {
+                break;
+            }
+            extra += 1;
+            pos += slot_len as u64;
+            prev_end = end;
+        }
+        Ok((extra > 0).then(|| files.len() as u32 + extra))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::arc::{ArcVersion, ArcWriter};
+
+    /// V1 archive with entries of 10, 20 and 30 bytes.
+    fn build() -> Vec<u8> {
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        for (name, len) in [("a", 10), ("b", 20), ("c", 30)] {
+            writer
+                .add_entry_raw(name.as_bytes(), &vec![0u8; len])
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Overwrite a `u32` field of V1 index slot `slot` (`field` 0 = offset,
+    /// 1 = size).
+    fn patch(data: &mut [u8], slot: usize, field: usize, value: u32) {
+        let pos = 16 + slot * 32 + 16 + field * 4;
+        data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn open(data: Vec<u8>) -> Arc<Cursor<Vec<u8>>> {
+        Arc::from_reader_unchecked(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn test_check_clean() {
+        let arc = open(build());
+        assert_eq!(arc.check().unwrap(), []);
+        assert_eq!(arc.check_decode(), []);
+    }
+
+    #[test]
+    fn test_check_problems() {
+        let mut data = build();
+        patch(&mut data, 1, 0, 5); // "b" starts inside "a"
+        patch(&mut data, 2, 1, 1000); // "c" runs past the end
+        data[16 + 2 * 32] = b'a'; // "c" renamed to "a"
+        let issues = open(data).check().unwrap();
+
+        // The bad size on the last entry must not read as a wrong count.
+        let data_offset = 16 + 3 * 32;
+        assert_eq!(
+            issues,
+            [
+                Issue::OutOfBounds {
+                    index: 2,
+                    offset: data_offset + 30,
+                    size: 1000,
+                    archive_len: data_offset + 60,
+                },
+                Issue::DuplicateName {
+                    index: 2,
+                    first: 0,
+                    name: "a".to_owned(),
+                },
+                Issue::Overlap {
+                    index: 1,
+                    other: 0,
+                    offset: data_offset + 5,
+                },
+                Issue::Gap {
+                    offset: data_offset + 25,
+                    len: 5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_count_mismatch() {
+        let mut data = build();
+        data[12..16].copy_from_slice(&2u32.to_le_bytes());
+        let issues = open(data).check().unwrap();
+        assert!(issues.contains(&Issue::CountMismatch {
+            declared: 2,
+            detected: 3,
+        }));
+
+        let mut data = build();
+        data[12..16].copy_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[0xff; 32]);
+        let issues = open(data).check().unwrap();
+        assert!(issues.contains(&Issue::CountMismatch {
+            declared: 4,
+            detected: 3,
+        }));
+    }
+
+    #[test]
+    fn test_check_cbg_first_entry() {
+        // The CBG header at the start of the data region reads as a
+        // plausible V1 slot: a printable name, width/height/bpp as
+        // offset/size and reserved zeros as padding.
+        let rgba: Vec<u8> = (0..16u8).flat_map(|v| [v, v / 2, 0x40, 0xFF]).collect();
+        let cbg = crate::cbg::encode_cbg_v1(&rgba, 16, 1, crate::write::PixelLayout::Bgr).unwrap();
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry_raw(b"cg", &cbg).unwrap();
+        writer.add_entry_raw(b"big", &vec![0u8; 0x2_0000]).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(&data[16 + 2 * 32..][..15], b"CompressedBG___");
+        assert_eq!(open(data).check().unwrap(), []);
+    }
+
+    #[test]
+    fn test_check_decode() {
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry_raw(b"ok", b"plain").unwrap();
+        writer
+            .add_entry_raw(
+                b"broken",
+                &[b"DSC FORMAT 1.00\0".as_slice(), &[0; 32]].concat(),
+            )
+            .unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        let issues = open(data).check_decode();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(issues[0], Issue::DecodeFailed { index: 1, .. }));
+    }
+
+    #[test]
+    fn test_check_decode_corrupt_payloads() {
+        use crate::{cbg, dsc, write::PixelLayout};
+
+        let text: Vec<u8> = (0..4096u32).map(|i| (i * i % 251) as u8).collect();
+        let mut truncated_dsc = dsc::encode_dsc(&text).unwrap();
+        truncated_dsc.truncate(truncated_dsc.len() / 2);
+
+        let rgba: Vec<u8> = (0..64u8).flat_map(|v| [v, v / 2, 0x40, 0xFF]).collect();
+        let mut bad_bpp = cbg::encode_cbg_v1(&rgba, 8, 8, PixelLayout::Bgr).unwrap();
+        bad_bpp[0x14..0x18].copy_from_slice(&0x100u32.to_le_bytes());
+        let mut truncated_v2 = cbg::encode_cbg_v2(&rgba, 8, 8, PixelLayout::Bgr, 90).unwrap();
+        truncated_v2.truncate(0x30 + 0x80 + 2);
+
+        let mut writer = ArcWriter::new(Cursor::new(Vec::new()), ArcVersion::V1).unwrap();
+        writer.add_entry_raw(b"ok", b"plain").unwrap();
+        writer.add_entry_raw(b"dsc", &truncated_dsc).unwrap();
+        writer.add_entry_raw(b"cbg1", &bad_bpp).unwrap();
+        writer.add_entry_raw(b"cbg2", &truncated_v2).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        let issues = open(data).check_decode();
+        let failed: Vec<u32> = issues
+            .iter()
+            .map(|issue| match issue {
+                Issue::DecodeFailed { index, .. } => *index,
+                other => panic!("unexpected issue {other:?}"),
+            })
+            .collect();
+        assert_eq!(failed, [1, 2, 3]);
+    }
+}
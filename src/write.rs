@@ -1,6 +1,6 @@
 use std::{
     fs::File,
-    io::{BufWriter, Cursor},
+    io::{BufWriter, Cursor, Write},
     path::Path,
 };
 
@@ -20,9 +20,22 @@ pub fn write_rgba_to_png(
     savepath: impl AsRef<Path>,
 ) -> ArcResult<()> {
     let file = File::create(savepath)?;
+    encode_png(width, height, array, BufWriter::new(file))
+}
 
-    let w = BufWriter::new(file);
+/// Encode RGBA pixel data as a PNG into memory
+pub fn rgba_to_png(width: ImageWidth, height: ImageHeight, array: &[u8]) -> ArcResult<Vec<u8>> {
+    let mut png = Vec::new();
+    encode_png(width, height, array, &mut png)?;
+    Ok(png)
+}
 
+fn encode_png(
+    width: ImageWidth,
+    height: ImageHeight,
+    array: &[u8],
+    w: impl Write,
+) -> ArcResult<()> {
     let mut encoder = png::Encoder::new(w, u32::from(width), u32::from(height));
     encoder.set_color(png::ColorType::Rgba);
     encoder.set_depth(png::BitDepth::Eight);
@@ -45,6 +58,9 @@ pub struct PngImage {
     pub rgba: Vec<u8>,
     /// `true` when at least one pixel has alpha < 255 (needs 32-bit storage).
     pub has_alpha: bool,
+    /// `true` when every pixel is opaque grey (R = G = B), so the image fits
+    /// in 8 bpp.
+    pub grayscale: bool,
 }
 
 /// Decode a PNG file into normalized RGBA8 pixels.
@@ -69,6 +85,7 @@ pub fn read_png(data: &[u8]) -> ArcResult<PngImage> {
     let rgba = convert_png_to_rgba(&buf, width, height, color_type);
 
     let has_alpha = rgba.chunks_exact(4).any(|px| px[3] != 0xFF);
+    let grayscale = !has_alpha && is_grayscale(&rgba);
 
     let (width_u16, height_u16) = (
         u16::try_from(width).map_err(|_| ArcError::PngUnsupported("PNG width exceeds u16::MAX"))?,
@@ -81,9 +98,16 @@ pub fn read_png(data: &[u8]) -> ArcResult<PngImage> {
         height: height_u16,
         rgba,
         has_alpha,
+        grayscale,
     })
 }
 
+/// `true` when every RGBA pixel has R = G = B.
+fn is_grayscale(rgba: &[u8]) -> bool {
+    rgba.chunks_exact(4)
+        .all(|px| px[0] == px[1] && px[1] == px[2])
+}
+
 /// Convert PNG decoded buffer (any supported color type) to RGBA8.
 fn convert_png_to_rgba(buf: &[u8], width: usize, height: usize, color_type: ColorType) -> Vec<u8> {
     let total = width * height;
@@ -114,6 +138,82 @@ fn convert_png_to_rgba(buf: &[u8], width: usize, height: usize, color_type: Colo
     }
 }
 
+// ---------------------------------------------------------------------------
+// Pixel layouts (shared by the BGI and CBG encoders)
+// ---------------------------------------------------------------------------
+
+/// Channel layout of the pixels stored in a BGI or CBG image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// 8 bpp, a single grey channel.
+    Gray,
+    /// 24 bpp: B, G, R per pixel.
+    Bgr,
+    /// 32 bpp: B, G, R, A per pixel.
+    Bgra,
+}
+
+impl PixelLayout {
+    /// Smallest layout that stores `rgba` losslessly.
+    #[must_use]
+    pub fn detect(rgba: &[u8]) -> Self {
+        if rgba.chunks_exact(4).any(|px| px[3] != 0xFF) {
+            Self::Bgra
+        } else if is_grayscale(rgba) {
+            Self::Gray
+        } else {
+            Self::Bgr
+        }
+    }
+
+    /// Layout stored with `bpp` bits per pixel, if it is 8, 24 or 32.
+    #[must_use]
+    pub const fn from_bpp(bpp: u16) -> Option<Self> {
+        match bpp {
+            8 => Some(Self::Gray),
+            24 => Some(Self::Bgr),
+            32 => Some(Self::Bgra),
+            _ => None,
+        }
+    }
+
+    /// Bits per pixel written to the image header.
+    #[must_use]
+    pub const fn bpp(self) -> u16 {
+        match self {
+            Self::Gray => 8,
+            Self::Bgr => 24,
+            Self::Bgra => 32,
+        }
+    }
+
+    /// Bytes per pixel.
+    #[must_use]
+    pub const fn pixel_size(self) -> usize {
+        self.bpp() as usize / 8
+    }
+
+    /// Convert RGBA8 pixels to this layout. Grey is the BT.601 luma, which is
+    /// exact for pixels whose channels are equal; alpha is dropped unless the
+    /// layout is [`PixelLayout::Bgra`].
+    #[must_use]
+    pub fn pack(self, rgba: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(rgba.len() / 4 * self.pixel_size());
+        for px in rgba.chunks_exact(4) {
+            let (r, g, b) = (u32::from(px[0]), u32::from(px[1]), u32::from(px[2]));
+            match self {
+                Self::Gray => {
+                    let luma = (r * 299 + g * 587 + b * 114 + 500) / 1000;
+                    out.push(u8::try_from(luma).unwrap_or(u8::MAX));
+                }
+                Self::Bgr => out.extend_from_slice(&[px[2], px[1], px[0]]),
+                Self::Bgra => out.extend_from_slice(&[px[2], px[1], px[0], px[3]]),
+            }
+        }
+        out
+    }
+}
+
 /// Convert raw BGR/BGRA/Grayscale pixel data to RGBA.
 ///
 /// Shared by BGI and CBG decoders. Input layout depends on `bpp`: